@@ -146,13 +146,13 @@ pub struct TwinkleAnim {
 }
 
 impl TwinkleAnim {
-    pub fn new(color: palette::Hsl, total_time: std::time::Duration) -> Self {
+    pub fn new(color: palette::Hsl, total_time: std::time::Duration, num_poles: usize) -> Self {
         let mut index : Vec<(usize,usize)> = vec![];
 
         let rand_pixel = rand::random::<usize>() % 32;
 
         for i in 0..5 {
-        let rand_pole = rand::random::<usize>() % super::NUM_POLES;
+        let rand_pole = rand::random::<usize>() % num_poles;
         // TODO make sure rand_pole is differnet each time., and rand pixel is not on the same line.
             index.push((rand_pole, rand_pixel));
         }
@@ -212,7 +212,8 @@ impl IdleAnim {
             (animator)(Box::new(TwinkleAnim::new(palette::Hsl::new(palette::RgbHue::from_radians(0.0),
                                                                1.0,
                                                                1.0),
-                                             std::time::Duration::from_millis(200))));
+                                             std::time::Duration::from_millis(200),
+                                             poles.len())));
         }
         self.add_circle_phase.cycle();
 
@@ -8,7 +8,6 @@ use std::ops::Rem;
 pub mod idle;
 pub mod touch;
 
-use super::NUM_POLES;
 
 const LED_ANIM_DURATION: u64 = 10;
 
@@ -50,6 +49,10 @@ impl AnimPhase {
         self.current_pos
     }
 
+    pub fn set_total_time(&mut self, total_time: std::time::Duration) {
+        self.total_time = to_float(total_time);
+    }
+
     pub fn is_done(&self) -> bool {
         self.current_pos >= 1.0
     }
@@ -91,6 +94,13 @@ impl Animator {
         }
     }
 
+    pub fn set_params(&mut self, params: &super::config::AnimParams) {
+        let secs = |s: f32| std::time::Duration::from_millis((s * 1000.) as u64);
+        self.heart_phase.set_total_time(secs(params.heart_period));
+        self.flower_phase.set_total_time(secs(params.flower_period));
+        self.disco_phase.set_total_time(secs(params.disco_period));
+    }
+
     pub fn animate_disco(&mut self, poles: &mut [super::Pole], delta: std::time::Duration) {
          self.disco_phase.update(delta);
 
@@ -101,7 +111,7 @@ impl Animator {
              return;
          }
 
-        for i in 0..(NUM_POLES / 2) {
+        for i in 0..(poles.len() / 2) {
             // TODO: randomize this
             let currentangle = 2. * std::f32::consts::PI  *0.3245251*(self.disco_state+i as f32);
             let hue = palette::Hsl::new(palette::RgbHue::from_radians(currentangle), 1., 0.5);
@@ -109,7 +119,7 @@ impl Animator {
             Self::draw_petal_cp2(poles, i , hue);
         }
         
-        for i in 0..(NUM_POLES / 2) {
+        for i in 0..(poles.len() / 2) {
             // TODO: randomize this
             let currentangle = 2. * std::f32::consts::PI  *0.3245251*(self.disco_state+5.+i as f32);
             let hue = palette::Hsl::new(palette::RgbHue::from_radians(currentangle), 1., 0.5);
@@ -121,24 +131,24 @@ impl Animator {
     pub fn animate_flower(&mut self, poles: &mut [super::Pole], delta: std::time::Duration) {
         let current = self.flower_phase.cyclic_update(delta);
 
-        for i in 0..(NUM_POLES / 2) {
+        for i in 0..(poles.len() / 2) {
             let string1 = i * 2;
 
             let currentangle = 2. * std::f32::consts::PI *
-                               (current + (string1 as f32) / (NUM_POLES as f32));
+                               (current + (string1 as f32) / (poles.len() as f32));
             let oppositeangle = currentangle - std::f32::consts::PI;
             let ophue = palette::Hsl::new(palette::RgbHue::from_radians(oppositeangle), 1., 0.5);
 
             Self::draw_petal_cp2(poles, i , ophue);
         }
 
-        for i in 0..(NUM_POLES / 2) {
+        for i in 0..(poles.len() / 2) {
 
             // first = 1p, second = 1m, third = 2p; petal = 1m + 2p
             let string1 = i * 2;
 
             let currentangle = 2. * std::f32::consts::PI *
-                               (current + (string1 as f32) / (NUM_POLES as f32));
+                               (current + (string1 as f32) / (poles.len() as f32));
             let curhue = palette::Hsl::new(palette::RgbHue::from_radians(currentangle), 1., 0.5);
             Self::draw_petal_cp1(poles, i , curhue);
 
@@ -149,7 +159,7 @@ impl Animator {
  
             // first = 1p, second = 1m, third = 2p; petal = 1m + 2p
             let string1 = index * 2;
-            let string2 = if index == 0 { NUM_POLES - 1 } else { string1 - 1 };
+            let string2 = (string1 + poles.len() - 1) % poles.len();
 
             {
                 let pole1 = poles[string1].leds_cp1();
@@ -169,10 +179,7 @@ pub fn draw_petal_cp2(poles: &mut [super::Pole],index : usize, c : palette::Hsl)
  
             // first = 1p, second = 1m, third = 2p; petal = 1m + 2p
             let string1 = index * 2;
-            let string2 = match index {
-                0...1 => NUM_POLES + string1 - 3,
-                _ => string1 - 3,
-            };
+            let string2 = (string1 + poles.len() - 3) % poles.len();
 
             {
                 let pole1 = poles[string1].leds_cp2();
@@ -196,7 +203,7 @@ pub fn draw_petal_cp2(poles: &mut [super::Pole],index : usize, c : palette::Hsl)
         let breath_phase = if val <= 0.5 {val} else {2.*0.5-val};
         let breath_phase = breath_phase * 2.0;
 
-        for i in 0..NUM_POLES {
+        for i in 0..poles.len() {
 
             let mut curhue = poles[i].base_color;
             curhue.lightness = 0.1+0.5*breath_phase;
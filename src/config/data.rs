@@ -0,0 +1,510 @@
+use std;
+
+use super::super::Modes;
+use super::ConfigError;
+use scheduler::Schedule;
+
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
+#[serde(default)]
+pub struct ConfigData {
+    pub num_leds_for_pole: usize,
+    pub cp1: usize,
+    pub cp2: usize,
+
+    #[serde(with = "range")]
+    pub heart: std::ops::Range<usize>,
+
+    pub mode: Modes,
+
+    /// poles that differ from the global values above
+    pub poles: Vec<PoleOverride>,
+
+    pub anim: AnimParams,
+
+    pub sensors: SensorConfig,
+
+    pub presets: std::collections::BTreeMap<String, Preset>,
+
+    pub schedule: Schedule,
+
+    /// only read on startup
+    pub network: NetworkConfig,
+
+    /// only read on startup
+    pub hardware: HardwareConfig,
+
+    /// only read on startup
+    pub installation: Installation,
+}
+
+impl ConfigData {
+    pub fn new() -> Self {
+        ConfigData {
+            num_leds_for_pole: 150,
+            cp1: 21,
+            cp2: 34,
+            heart: 54..(54+14),
+            mode: Modes::Regular,
+            poles: vec![],
+            anim: AnimParams::new(),
+            sensors: SensorConfig::new(),
+            presets: std::collections::BTreeMap::new(),
+            schedule: Schedule::new(),
+            network: NetworkConfig::new(),
+            hardware: HardwareConfig::new(),
+            installation: Installation::new(),
+        }
+    }
+
+    pub fn apply_preset(&mut self, preset: &Preset) {
+        self.num_leds_for_pole = preset.num_leds_for_pole;
+        self.cp1 = preset.cp1;
+        self.cp2 = preset.cp2;
+        self.heart = preset.heart.clone();
+        self.mode = preset.mode;
+        self.poles = preset.poles.clone();
+        self.anim = preset.anim.clone();
+    }
+
+    /// the geometry of a pole: its override if it has one, the global values otherwise
+    pub fn pole_geometry(&self, pole: usize) -> PoleGeometry {
+        let mut geometry = PoleGeometry {
+            num_leds_for_pole: self.num_leds_for_pole,
+            cp1: self.cp1,
+            cp2: self.cp2,
+            heart: self.heart.clone(),
+        };
+
+        if let Some(o) = self.poles.iter().find(|o| o.pole == pole) {
+            geometry.num_leds_for_pole = o.num_leds_for_pole.unwrap_or(geometry.num_leds_for_pole);
+            geometry.cp1 = o.cp1.unwrap_or(geometry.cp1);
+            geometry.cp2 = o.cp2.unwrap_or(geometry.cp2);
+            if let Some((start, end)) = o.heart {
+                geometry.heart = start..end;
+            }
+        }
+        geometry
+    }
+
+    /// the override of a pole, created empty if it doesn't have one yet
+    pub fn pole_override(&mut self, pole: usize) -> &mut PoleOverride {
+        if let Some(pos) = self.poles.iter().position(|o| o.pole == pole) {
+            return &mut self.poles[pos];
+        }
+        self.poles.push(PoleOverride { pole: pole, ..Default::default() });
+        self.poles.last_mut().unwrap()
+    }
+
+    pub fn remove_pole_override(&mut self, pole: usize) {
+        self.poles.retain(|o| o.pole != pole);
+    }
+
+    /// true if the two configs only differ in their mode
+    pub fn same_except_mode(&self, other: &ConfigData) -> bool {
+        let mut other = other.clone();
+        other.mode = self.mode;
+        *self == other
+    }
+
+    /// every config, from the file or changed at runtime, is checked here
+    /// before it is used.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.installation.validate()?;
+        let num_poles = self.installation.num_poles;
+        if let Some(o) = self.poles.iter().find(|o| o.pole >= num_poles) {
+            return Err(ConfigError::UnknownPole(o.pole));
+        }
+        for i in 0..num_poles {
+            self.pole_geometry(i).validate(i, self.installation.leds_per_strip)?;
+        }
+        self.anim.validate()?;
+        self.sensors.validate(num_poles)?;
+        self.schedule.validate()
+    }
+}
+
+impl Default for ConfigData {
+    fn default() -> Self {
+        ConfigData::new()
+    }
+}
+
+#[derive(Clone,Debug,Default,PartialEq,Serialize,Deserialize)]
+pub struct PoleOverride {
+    pub pole: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_leds_for_pole: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cp1: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cp2: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heart: Option<(usize, usize)>,
+}
+
+/// the size of the installation. everything that holds per pole state
+/// is sized from this on startup.
+#[derive(Clone,Copy,Debug,PartialEq,Serialize,Deserialize)]
+#[serde(default)]
+pub struct Installation {
+    pub num_poles: usize,
+    pub leds_per_strip: usize,
+}
+
+/// the pole index in a /pole_touch packet is a single byte
+#[cfg(not(feature = "ledscape"))]
+const MAX_POLES: usize = 256;
+/// a strip per pole, and the ledscape drives no more strips than this
+#[cfg(feature = "ledscape")]
+const MAX_POLES: usize = ::ledscape::LEDSCAPE_NUM_STRIPS;
+
+impl Installation {
+    pub fn new() -> Self {
+        Installation {
+            num_poles: 20,
+            leds_per_strip: 150,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        // petals take two poles, and the flower needs a few petals
+        if self.num_poles < 4 {
+            return Err(ConfigError::InvalidInstallation(format!("{} poles is too few",
+                                                                self.num_poles)));
+        }
+        if self.num_poles > MAX_POLES {
+            return Err(ConfigError::InvalidInstallation(format!("{} poles is too many, {} at most",
+                                                                self.num_poles,
+                                                                MAX_POLES)));
+        }
+        if self.leds_per_strip == 0 {
+            return Err(ConfigError::InvalidInstallation("a strip needs leds".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Installation {
+    fn default() -> Self {
+        Installation::new()
+    }
+}
+
+/// addresses to listen on and to connect to. the command line and the
+/// environment take precedence, see settings.rs.
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// udp address for pole touches from the wifi nodes
+    pub touch_listen: String,
+    /// udp address of the osc config server
+    pub config_listen: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub osc_server: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opc_server: Option<String>,
+    /// the console on a socket: "unix:<path>" and/or tcp addresses, comma separated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub control_listen: Option<String>,
+    /// tcp address of the json api
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_listen: Option<String>,
+    /// host:port of the mqtt broker to bridge to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mqtt_broker: Option<String>,
+    /// the prefix of the mqtt topics
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mqtt_topic: Option<String>,
+}
+
+impl NetworkConfig {
+    pub fn new() -> Self {
+        NetworkConfig {
+            touch_listen: "0.0.0.0:3134".to_string(),
+            config_listen: "0.0.0.0:8134".to_string(),
+            osc_server: None,
+            opc_server: None,
+            control_listen: None,
+            http_listen: None,
+            mqtt_broker: None,
+            mqtt_topic: None,
+        }
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig::new()
+    }
+}
+
+/// the led and touch hardware. unset values fall back to the environment and
+/// the built in defaults, see settings.rs.
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
+#[serde(default)]
+pub struct HardwareConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rgb: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout: Option<String>,
+}
+
+impl HardwareConfig {
+    pub fn new() -> Self {
+        HardwareConfig {
+            device: None,
+            rgb: None,
+            layout: None,
+        }
+    }
+}
+
+impl Default for HardwareConfig {
+    fn default() -> Self {
+        HardwareConfig::new()
+    }
+}
+
+/// how fast the animations run, in seconds per cycle
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
+#[serde(default)]
+pub struct AnimParams {
+    pub heart_period: f32,
+    pub flower_period: f32,
+    pub disco_period: f32,
+}
+
+impl AnimParams {
+    pub fn new() -> Self {
+        AnimParams {
+            heart_period: 5.,
+            flower_period: 10.,
+            disco_period: 0.5,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let periods = [("heart_period", self.heart_period),
+                       ("flower_period", self.flower_period),
+                       ("disco_period", self.disco_period)];
+        for &(name, period) in periods.iter() {
+            if !(period > 0.) {
+                return Err(ConfigError::InvalidPeriod(name.to_string(), period));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for AnimParams {
+    fn default() -> Self {
+        AnimParams::new()
+    }
+}
+
+/// when touch sensors are considered gone, in seconds
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
+#[serde(default)]
+pub struct SensorConfig {
+    /// a pole the serial touch controller last heard of this long ago is offline
+    pub serial_stale_after: f32,
+    /// a wifi pole node that sent nothing for this long is offline
+    pub udp_timeout: f32,
+    pub debounce: Debounce,
+}
+
+impl SensorConfig {
+    pub fn new() -> Self {
+        SensorConfig {
+            serial_stale_after: 5.,
+            udp_timeout: 10.,
+            debounce: Debounce::new(),
+        }
+    }
+
+    pub fn validate(&self, num_poles: usize) -> Result<(), ConfigError> {
+        let periods = [("serial_stale_after", self.serial_stale_after),
+                       ("udp_timeout", self.udp_timeout)];
+        for &(name, period) in periods.iter() {
+            if !(period > 0.) {
+                return Err(ConfigError::InvalidPeriod(name.to_string(), period));
+            }
+        }
+        self.debounce.validate(num_poles)
+    }
+}
+
+impl Default for SensorConfig {
+    fn default() -> Self {
+        SensorConfig::new()
+    }
+}
+
+/// how long a touch must last before it starts, and how long it lingers after
+/// it stops, in seconds. a flicker shorter than release doesn't stop the touch.
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
+#[serde(default)]
+pub struct Debounce {
+    pub hold: f32,
+    pub release: f32,
+    /// poles that differ from the values above
+    pub poles: Vec<PoleDebounce>,
+}
+
+impl Debounce {
+    pub fn new() -> Self {
+        Debounce {
+            hold: 0.,
+            release: 0.,
+            poles: vec![],
+        }
+    }
+
+    /// the hold and release of a connection between two poles: the longer of the two
+    pub fn for_poles(&self, pole1: usize, pole2: usize) -> (f32, f32) {
+        let pole_values = |pole: usize| {
+            let o = self.poles.iter().find(|o| o.pole == pole);
+            (o.and_then(|o| o.hold).unwrap_or(self.hold),
+             o.and_then(|o| o.release).unwrap_or(self.release))
+        };
+        let (hold1, release1) = pole_values(pole1);
+        let (hold2, release2) = pole_values(pole2);
+        (hold1.max(hold2), release1.max(release2))
+    }
+
+    pub fn validate(&self, num_poles: usize) -> Result<(), ConfigError> {
+        if let Some(o) = self.poles.iter().find(|o| o.pole >= num_poles) {
+            return Err(ConfigError::UnknownPole(o.pole));
+        }
+        let mut delays = vec![self.hold, self.release];
+        for o in self.poles.iter() {
+            delays.extend(o.hold);
+            delays.extend(o.release);
+        }
+        for delay in delays {
+            if !(delay >= 0.) {
+                return Err(ConfigError::InvalidPeriod("debounce".to_string(), delay));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Debounce {
+    fn default() -> Self {
+        Debounce::new()
+    }
+}
+
+#[derive(Clone,Debug,Default,PartialEq,Serialize,Deserialize)]
+pub struct PoleDebounce {
+    pub pole: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hold: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release: Option<f32>,
+}
+
+/// a named snapshot of the mode, the geometry and the animation params
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
+#[serde(default)]
+pub struct Preset {
+    pub mode: Modes,
+    pub num_leds_for_pole: usize,
+    pub cp1: usize,
+    pub cp2: usize,
+    #[serde(with = "range")]
+    pub heart: std::ops::Range<usize>,
+    pub poles: Vec<PoleOverride>,
+    pub anim: AnimParams,
+}
+
+impl Preset {
+    pub fn from_config(data: &ConfigData) -> Self {
+        Preset {
+            mode: data.mode,
+            num_leds_for_pole: data.num_leds_for_pole,
+            cp1: data.cp1,
+            cp2: data.cp2,
+            heart: data.heart.clone(),
+            poles: data.poles.clone(),
+            anim: data.anim.clone(),
+        }
+    }
+}
+
+impl Default for Preset {
+    fn default() -> Self {
+        Preset::from_config(&ConfigData::new())
+    }
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct PoleGeometry {
+    pub num_leds_for_pole: usize,
+    pub cp1: usize,
+    pub cp2: usize,
+    pub heart: std::ops::Range<usize>,
+}
+
+impl PoleGeometry {
+    /// cp1 <= cp2 <= pole length <= strip length, and the heart inside the pole.
+    pub fn validate(&self, pole: usize, leds_per_strip: usize) -> Result<(), ConfigError> {
+        if self.num_leds_for_pole > leds_per_strip {
+            return Err(ConfigError::PoleTooLong {
+                pole: pole,
+                num_leds: self.num_leds_for_pole,
+                max: leds_per_strip,
+            });
+        }
+        if self.cp1 > self.cp2 {
+            return Err(ConfigError::ControlPointsReversed {
+                pole: pole,
+                cp1: self.cp1,
+                cp2: self.cp2,
+            });
+        }
+        if self.cp2 > self.num_leds_for_pole {
+            return Err(ConfigError::ControlPointOutsidePole {
+                pole: pole,
+                cp2: self.cp2,
+                num_leds: self.num_leds_for_pole,
+            });
+        }
+        if self.heart.start > self.heart.end {
+            return Err(ConfigError::HeartReversed {
+                pole: pole,
+                heart: self.heart.clone(),
+            });
+        }
+        if self.heart.end > self.num_leds_for_pole {
+            return Err(ConfigError::HeartOutsidePole {
+                pole: pole,
+                heart: self.heart.clone(),
+                num_leds: self.num_leds_for_pole,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// serde helpers to store a range as a two element array: [start, end]
+mod range {
+    use std;
+    use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+    pub fn serialize<S>(r: &std::ops::Range<usize>, s: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        (r.start, r.end).serialize(s)
+    }
+
+    pub fn deserialize<'de, D>(d: D) -> Result<std::ops::Range<usize>, D::Error>
+        where D: Deserializer<'de>
+    {
+        let (start, end) = <(usize, usize)>::deserialize(d)?;
+        Ok(start..end)
+    }
+}
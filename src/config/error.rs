@@ -0,0 +1,79 @@
+use std;
+
+/// why a config change was rejected
+#[derive(Clone,Debug,PartialEq)]
+pub enum ConfigError {
+    /// the pole is longer than its led strip
+    PoleTooLong { pole: usize, num_leds: usize, max: usize },
+    /// cp1 must not be above cp2
+    ControlPointsReversed { pole: usize, cp1: usize, cp2: usize },
+    /// cp2 must be inside the pole
+    ControlPointOutsidePole { pole: usize, cp2: usize, num_leds: usize },
+    /// the heart must start before it ends
+    HeartReversed { pole: usize, heart: std::ops::Range<usize> },
+    /// the heart must be inside the pole
+    HeartOutsidePole { pole: usize, heart: std::ops::Range<usize>, num_leds: usize },
+    /// an override for a pole that doesn't exist
+    UnknownPole(usize),
+    /// animation periods must be positive
+    InvalidPeriod(String, f32),
+    /// there is no preset with this name
+    UnknownPreset(String),
+    /// brightness goes from 0 to 1
+    InvalidBrightness(f32),
+    /// the number of poles or leds doesn't make sense
+    InvalidInstallation(String),
+    /// the arguments of a change are missing or not numbers
+    InvalidArgs(String),
+    /// there is no earlier config to go back to
+    NoHistory,
+    /// the revision was never made, or dropped from the history
+    UnknownRevision(usize),
+    /// there is no setter for this key
+    UnknownKey(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            ConfigError::PoleTooLong { pole, num_leds, max } => {
+                write!(f, "pole {}: length {} is above the strip length {}", pole, num_leds, max)
+            }
+            ConfigError::ControlPointsReversed { pole, cp1, cp2 } => {
+                write!(f, "pole {}: cp1 {} is above cp2 {}", pole, cp1, cp2)
+            }
+            ConfigError::ControlPointOutsidePole { pole, cp2, num_leds } => {
+                write!(f, "pole {}: cp2 {} is above the pole length {}", pole, cp2, num_leds)
+            }
+            ConfigError::HeartReversed { pole, ref heart } => {
+                write!(f, "pole {}: heart {:?} ends before it starts", pole, heart)
+            }
+            ConfigError::HeartOutsidePole { pole, ref heart, num_leds } => {
+                write!(f,
+                       "pole {}: heart {:?} doesn't fit in the pole length {}",
+                       pole,
+                       heart,
+                       num_leds)
+            }
+            ConfigError::UnknownPole(pole) => write!(f, "there is no pole {}", pole),
+            ConfigError::InvalidPeriod(ref name, period) => {
+                write!(f, "{} must be positive, not {}", name, period)
+            }
+            ConfigError::UnknownPreset(ref name) => write!(f, "there is no preset {:?}", name),
+            ConfigError::InvalidBrightness(brightness) => {
+                write!(f, "brightness {} is not between 0 and 1", brightness)
+            }
+            ConfigError::InvalidInstallation(ref why) => write!(f, "invalid installation: {}", why),
+            ConfigError::InvalidArgs(ref args) => write!(f, "invalid arguments {}", args),
+            ConfigError::NoHistory => write!(f, "nothing to undo"),
+            ConfigError::UnknownRevision(id) => write!(f, "there is no revision {}", id),
+            ConfigError::UnknownKey(ref key) => write!(f, "there is no config key {:?}", key),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn description(&self) -> &str {
+        "invalid config"
+    }
+}
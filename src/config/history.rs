@@ -0,0 +1,65 @@
+use std;
+
+use super::ConfigData;
+
+/// how many revisions are kept before the oldest is dropped
+const MAX_REVISIONS: usize = 50;
+
+/// the config as it was before a change
+#[derive(Clone,Debug)]
+pub struct Revision {
+    /// increases with each change, so a revision keeps its number while newer ones come and go
+    pub id: usize,
+    pub time: std::time::SystemTime,
+    /// who made the change that replaced this revision, e.g. "osc 192.168.1.20:9000"
+    pub source: String,
+    pub data: ConfigData,
+}
+
+pub struct History {
+    revisions: std::collections::VecDeque<Revision>,
+    next_id: usize,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            revisions: std::collections::VecDeque::new(),
+            next_id: 0,
+        }
+    }
+
+    /// remember data, which source is about to replace
+    pub fn push(&mut self, data: ConfigData, source: &str) {
+        if self.revisions.len() == MAX_REVISIONS {
+            self.revisions.pop_front();
+        }
+        self.revisions.push_back(Revision {
+            id: self.next_id,
+            time: std::time::SystemTime::now(),
+            source: source.to_string(),
+            data: data,
+        });
+        self.next_id += 1;
+    }
+
+    /// take the latest revision out of the history
+    pub fn pop(&mut self) -> Option<Revision> {
+        self.revisions.pop_back()
+    }
+
+    /// take revision id and everything newer out of the history
+    pub fn rewind(&mut self, id: usize) -> Option<Revision> {
+        let pos = match self.revisions.iter().position(|r| r.id == id) {
+            Some(pos) => pos,
+            None => return None,
+        };
+        let mut newer = self.revisions.split_off(pos);
+        newer.pop_front()
+    }
+
+    /// oldest first
+    pub fn revisions(&self) -> Vec<Revision> {
+        self.revisions.iter().cloned().collect()
+    }
+}
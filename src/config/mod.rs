@@ -1,185 +1,484 @@
 use std;
-use std::net::UdpSocket;
+use std::io::Write;
+use serde_json;
 use rosc;
 
 use super::{Events, Modes};
+use record::Recorder;
+use scheduler::Schedule;
+use status::StatusHandle;
 
-struct ConfigData {
-    num_leds_for_pole: usize,
-    cp1: usize,
-    cp2: usize,
+mod data;
+mod error;
+mod history;
+mod server;
 
-    heart: std::ops::Range<usize>
+use self::data::{ConfigData, Preset};
+pub use self::data::{AnimParams, Installation, NetworkConfig, PoleGeometry, SensorConfig};
+pub use self::error::ConfigError;
+pub use self::history::Revision;
+use self::history::History;
+
+/// shared access to the config for the other threads (osc server, consoles).
+/// all runtime changes go through it.
+#[derive(Clone)]
+pub struct ConfigHandle {
+    data: std::sync::Arc<std::sync::RwLock<ConfigData>>,
+    history: std::sync::Arc<std::sync::Mutex<History>>,
+    sender: std::sync::mpsc::Sender<Events>,
+    /// records the events sent through the handle, under its source
+    recorder: Option<Recorder>,
+    /// recorded in the history with each change made through this handle
+    source: String,
 }
 
-impl ConfigData {
-    fn new() -> Self {
-        ConfigData {
-            num_leds_for_pole: 150,
-            cp1: 21,
-            cp2: 34,
-            heart: 54..(54+14),
+impl ConfigHandle {
+    /// a handle whose changes are recorded as coming from source
+    pub fn with_source(&self, source: &str) -> ConfigHandle {
+        ConfigHandle { source: source.to_string(), ..self.clone() }
+    }
+
+    pub fn get_pole_geometry(&self, pole: usize) -> PoleGeometry {
+        self.data.read().unwrap().pole_geometry(pole)
+    }
+
+    /// send an event to the work loop, recorded as coming from the handle's source
+    fn send(&self, event: Events) {
+        if let Some(ref recorder) = self.recorder {
+            recorder.record(&self.source, event);
         }
+        self.sender.send(event);
+    }
+
+    pub fn get_mode(&self) -> Modes {
+        self.data.read().unwrap().mode
+    }
+
+    pub fn get_anim_params(&self) -> AnimParams {
+        self.data.read().unwrap().anim.clone()
+    }
+
+    pub fn get_installation(&self) -> Installation {
+        self.data.read().unwrap().installation
+    }
+
+    pub fn get_sensors(&self) -> SensorConfig {
+        self.data.read().unwrap().sensors.clone()
+    }
+
+    pub fn get_schedule(&self) -> Schedule {
+        self.data.read().unwrap().schedule.clone()
+    }
+
+    pub fn get_preset_names(&self) -> Vec<String> {
+        self.data.read().unwrap().presets.keys().cloned().collect()
+    }
+
+    /// snapshot the current mode and parameters under name, and save the config file
+    pub fn save_preset(&self, name: &str) -> Result<(), ConfigError> {
+        self.change(|d| {
+            let preset = Preset::from_config(d);
+            d.presets.insert(name.to_string(), preset);
+        })?;
+        self.send(Events::SaveConfig);
+        Ok(())
+    }
+
+    pub fn load_preset(&self, name: &str) -> Result<(), ConfigError> {
+        let preset = self.data.read().unwrap().presets.get(name).cloned();
+        let preset = match preset {
+            Some(preset) => preset,
+            None => return Err(ConfigError::UnknownPreset(name.to_string())),
+        };
+
+        self.change(|d| d.apply_preset(&preset))?;
+        self.send(Events::ModeChanged(preset.mode));
+        Ok(())
+    }
+
+    /// the revisions that can be restored, oldest first
+    pub fn get_history(&self) -> Vec<Revision> {
+        self.history.lock().unwrap().revisions()
+    }
+
+    /// go back to the config before the last change
+    pub fn undo(&self) -> Result<(), ConfigError> {
+        let revision = self.history.lock().unwrap().pop();
+        match revision {
+            Some(revision) => self.restore(revision),
+            None => Err(ConfigError::NoHistory),
+        }
+    }
+
+    /// go back to revision id, dropping the revisions after it
+    pub fn revert(&self, id: usize) -> Result<(), ConfigError> {
+        let revision = self.history.lock().unwrap().rewind(id);
+        match revision {
+            Some(revision) => self.restore(revision),
+            None => Err(ConfigError::UnknownRevision(id)),
+        }
+    }
+
+    /// the whole config, as it is saved
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(&*self.data.read().unwrap()).expect("the config is plain data")
+    }
+
+    /// change the config with a json merge patch: objects are merged key by key, null
+    /// puts a key back to its default and anything else replaces the value.
+    pub fn patch(&self, patch: &serde_json::Value) -> Result<(), ConfigError> {
+        let mut newmode = None;
+        // merged under the lock, so a change that comes in meanwhile isn't lost
+        self.try_change(|d| {
+            let mut json = serde_json::to_value(&*d).expect("the config is plain data");
+            merge(&mut json, patch);
+            let newdata: ConfigData = serde_json::from_value(json)
+                .map_err(|e| ConfigError::InvalidArgs(e.to_string()))?;
+            if newdata.installation != d.installation {
+                let why = "it can only change on a restart".to_string();
+                return Err(ConfigError::InvalidInstallation(why));
+            }
+            if d.mode != newdata.mode {
+                newmode = Some(newdata.mode);
+            }
+            *d = newdata;
+            Ok(())
+        })?;
+        if let Some(mode) = newmode {
+            self.send(Events::ModeChanged(mode));
+        }
+        Ok(())
+    }
+
+    /// change a config key from text, as typed on the console. keys are the
+    /// addresses of the osc setters without the leading /: cp1, heart, pole/3/cp2...
+    pub fn set(&self, key: &str, values: &[&str]) -> Result<(), ConfigError> {
+        let args: Vec<rosc::OscType> = values.iter()
+            .map(|v| if let Ok(n) = v.parse::<i32>() {
+                rosc::OscType::Int(n)
+            } else if let Ok(n) = v.parse::<f32>() {
+                rosc::OscType::Float(n)
+            } else {
+                rosc::OscType::String(v.to_string())
+            })
+            .collect();
+        let addr = format!("/{}", key.trim_left_matches('/'));
+        match server::process_setter(self, &addr, Some(&args)) {
+            Some(res) => res,
+            None => Err(ConfigError::UnknownKey(key.to_string())),
+        }
+    }
+
+    fn restore(&self, revision: Revision) -> Result<(), ConfigError> {
+        info!("{} restores the config from before the change by {}",
+              self.source,
+              revision.source);
+        {
+            let mut data = self.data.write().unwrap();
+            let mut newdata = revision.data;
+            // the installation can't change at runtime, and the mode changes
+            // without a revision (scheduler, mode events), so the revision's
+            // mode is just whatever was active back then
+            newdata.installation = data.installation;
+            newdata.mode = data.mode;
+            newdata.validate()?;
+            *data = newdata;
+        }
+
+        self.send(Events::ConfigChanged);
+        Ok(())
+    }
+
+    /// apply a change to a copy of the config, and publish the copy if it is valid.
+    /// the previous config goes to the history.
+    fn change<F>(&self, f: F) -> Result<(), ConfigError>
+        where F: FnOnce(&mut ConfigData)
+    {
+        self.try_change(|d| {
+            f(d);
+            Ok(())
+        })
+    }
+
+    /// change, for changes that can fail before the config is validated
+    fn try_change<F>(&self, f: F) -> Result<(), ConfigError>
+        where F: FnOnce(&mut ConfigData) -> Result<(), ConfigError>
+    {
+        {
+            let mut data = self.data.write().unwrap();
+            let mut newdata = data.clone();
+            f(&mut newdata)?;
+            newdata.validate()?;
+            let olddata = std::mem::replace(&mut *data, newdata);
+            self.history.lock().unwrap().push(olddata, &self.source);
+        }
+
+        self.send(Events::ConfigChanged);
+        Ok(())
     }
 }
 
 pub struct Config {
-    data: std::sync::Arc<std::sync::RwLock<ConfigData>>,
+    handle: ConfigHandle,
     configfile : std::path::PathBuf,
 }
 
 impl Config {
-    pub fn new(configfile : &std::path::Path,sender: std::sync::mpsc::Sender<Events>) -> Self {
-        let configdata = std::sync::Arc::new(std::sync::RwLock::new(ConfigData::new()));
-        let mut s = Config { data: configdata.clone() , configfile : std::path::PathBuf::from(configfile)};
+    /// installation comes from the settings and replaces the one in the file
+    /// the events sent by the handles are recorded to recorder, if there is one
+    pub fn new(configfile : &std::path::Path,
+               installation: Installation,
+               sender: std::sync::mpsc::Sender<Events>,
+               recorder: Option<Recorder>) -> Self {
+        let handle = ConfigHandle {
+            data: std::sync::Arc::new(std::sync::RwLock::new(ConfigData::new())),
+            history: std::sync::Arc::new(std::sync::Mutex::new(History::new())),
+            sender: sender,
+            recorder: recorder,
+            source: "startup".to_string(),
+        };
+        let mut s = Config {
+            handle: handle,
+            configfile: std::path::PathBuf::from(configfile),
+        };
 
-        if s.configfile.exists() {
-            s.load_config();
-        }
+        let loaded = s.load_config(installation);
 
         // generate config change event for the initial config
-        sender.send(Events::ConfigChanged);
+        s.handle.send(Events::ConfigChanged);
+        s.handle.send(Events::ModeChanged(s.get_mode()));
+
+        let watchfile = s.configfile.clone();
+        let watchhandle = s.handle.with_source("config file");
+        std::thread::spawn(move || Self::watch_config(watchfile, watchhandle, loaded));
 
-        std::thread::spawn(move || Self::start_config_server(sender, configdata));
         s
     }
-    
-    fn load_config(&mut self) {
-   //     unimplemented!()
-    }
-    pub fn save_config(&mut self) {
-     //   unimplemented!()
-    }
 
-    pub fn get_num_leds_for_pole(&self) -> usize {
-        let data = self.data.read().unwrap();
-        let numret = data.num_leds_for_pole;
-        numret
+    /// listen for osc config messages on addr
+    pub fn start_server(&self, addr: &str, status: StatusHandle) -> std::io::Result<()> {
+        let socket = std::net::UdpSocket::bind(addr)?;
+        let handle = self.handle.with_source("osc");
+        std::thread::spawn(move || server::start_config_server(handle, status, socket));
+        Ok(())
     }
 
-    pub fn get_cp1(&self) -> usize {
-        self.data.read().unwrap().cp1
+    pub fn handle(&self) -> ConfigHandle {
+        self.handle.clone()
+    }
+    
+    /// returns what was loaded, for the watcher to tell what changes in the file
+    fn load_config(&mut self, installation: Installation) -> ConfigData {
+        let newdata = match Self::read_config(&self.configfile, installation) {
+            Ok(newdata) => {
+                info!("loaded config from {:?}", self.configfile);
+                newdata
+            }
+            Err(e) => {
+                warn!("can't load config from {:?}, using defaults: {}", self.configfile, e);
+                let mut data = ConfigData::new();
+                data.installation = installation;
+                data
+            }
+        };
+        *self.handle.data.write().unwrap() = newdata.clone();
+        newdata
     }
 
-    pub fn get_cp2(&self) -> usize {
-        self.data.read().unwrap().cp2
+    pub fn save_config(&mut self) {
+        match self.write_config() {
+            Ok(()) => info!("saved config to {:?}", self.configfile),
+            Err(e) => error!("can't save config to {:?}: {}", self.configfile, e),
+        }
     }
 
-    pub fn get_heart(&self) -> std::ops::Range<usize> {
-        self.data.read().unwrap().heart.clone()
+    /// the file is checked against the running installation, not its own
+    fn read_config(path: &std::path::Path,
+                   installation: Installation)
+                   -> std::io::Result<ConfigData> {
+        let file = std::fs::File::open(path)?;
+        let mut data: ConfigData = serde_json::from_reader(file)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        data.installation = installation;
+        data.validate().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        Ok(data)
     }
 
-    fn start_config_server(sender: std::sync::mpsc::Sender<Events>,
-                           mut data: std::sync::Arc<std::sync::RwLock<ConfigData>>) {
-        let mut socket = UdpSocket::bind("0.0.0.0:8134").expect("this must work");
-        info!("osc config server up");
-        let mut buf = [0; 4096];
+    /// poll the config file and reload it when it changes on disk.
+    /// loaded is what the file held the last time it was read.
+    fn watch_config(configfile: std::path::PathBuf, handle: ConfigHandle, mut loaded: ConfigData) {
+        let modified = |path: &std::path::Path| {
+            std::fs::metadata(path).and_then(|m| m.modified()).ok()
+        };
+
+        let mut last_modified = modified(&configfile);
         loop {
-            let res = socket.recv_from(&mut buf);
-            if res.is_err() {
-                // TODO log
+            std::thread::sleep(std::time::Duration::from_secs(1));
+            let current = modified(&configfile);
+            if current == last_modified {
+                continue;
+            }
+            last_modified = current;
+            // file removed (or mid rename); keep what we have
+            if current.is_none() {
                 continue;
             }
-            let (amt, src) = res.unwrap();
-            let buf = &mut buf[..amt];
 
-            let res = rosc::decoder::decode(&buf);
-            let msg = match res {
-                Err(_) => {
+            info!("config file {:?} changed, reloading", configfile);
+            Self::reload_config(&configfile, &handle, &mut loaded);
+        }
+    }
 
-                // TODO log
-                continue;
+    /// apply only what was edited in the file since it was last loaded, so
+    /// changes made at runtime (the mode above all) stay unless the file
+    /// itself changes them
+    fn reload_config(configfile: &std::path::Path,
+                     handle: &ConfigHandle,
+                     loaded: &mut ConfigData) {
+        let filedata = match Self::read_config(configfile, loaded.installation) {
+            Ok(filedata) => filedata,
+            Err(e) => {
+                warn!("keeping the previous config, can't load {:?}: {}", configfile, e);
+                return;
+            }
+        };
+
+        let (olddata, newdata) = {
+            let mut data = handle.data.write().unwrap();
+            let mut json = serde_json::to_value(&*data).expect("the config is plain data");
+            apply_changes(&mut json,
+                          &serde_json::to_value(&*loaded).expect("the config is plain data"),
+                          &serde_json::to_value(&filedata).expect("the config is plain data"));
+            let newdata: ConfigData = match serde_json::from_value(json) {
+                Ok(newdata) => newdata,
+                Err(e) => {
+                    warn!("keeping the previous config, can't apply {:?}: {}", configfile, e);
+                    return;
                 }
-                Ok(msg) => msg,
             };
+            if let Err(e) = newdata.validate() {
+                warn!("keeping the previous config, {:?} doesn't fit the running installation: {}",
+                      configfile,
+                      e);
+                return;
+            }
+            if *data == newdata {
+                // e.g. our own /saveconfig
+                *loaded = filedata;
+                return;
+            }
+            let olddata = std::mem::replace(&mut *data, newdata.clone());
+            handle.history.lock().unwrap().push(olddata.clone(), &handle.source);
+            (olddata, newdata)
+        };
+        *loaded = filedata;
 
-            debug!("got osc packet {:?}", &msg);
-            Self::process(&mut data, &sender, msg);
+        // only notify about what actually changed
+        if !olddata.same_except_mode(&newdata) {
+            handle.send(Events::ConfigChanged);
+        }
+        if olddata.mode != newdata.mode {
+            handle.send(Events::ModeChanged(newdata.mode));
         }
-
     }
 
+    fn write_config(&self) -> std::io::Result<()> {
+        let data = self.handle.data.read().unwrap().clone();
 
-    fn process(data: &mut std::sync::Arc<std::sync::RwLock<ConfigData>>,
-               sender: &std::sync::mpsc::Sender<Events>,
-               p: rosc::OscPacket) {
-        match p {
-            rosc::OscPacket::Message(m) => {
-                Self::process_message(data, sender, m);
-            }
-            rosc::OscPacket::Bundle(b) => {
-                // we ignore time tag. sorry.
-                for inner in b.content {
-                    Self::process(data, sender, inner);
-                }
+        // write to a temp file next to the config and rename it over the old one,
+        // so a power cut mid save leaves either the old or the new file on disk.
+        let mut tmpfile = self.configfile.clone().into_os_string();
+        tmpfile.push(".tmp");
+        let tmpfile = std::path::PathBuf::from(tmpfile);
+        {
+            let mut f = std::fs::File::create(&tmpfile)?;
+            serde_json::to_writer_pretty(&mut f, &data)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            f.write_all(b"\n")?;
+            f.sync_all()?;
+        }
+        std::fs::rename(&tmpfile, &self.configfile)?;
+
+        // make the rename itself durable
+        if let Some(dir) = self.configfile.parent() {
+            if let Ok(dir) = std::fs::File::open(dir) {
+                let _ = dir.sync_all();
             }
         }
+        Ok(())
     }
 
-    fn process_message(data: &mut std::sync::Arc<std::sync::RwLock<ConfigData>>,
-                       sender: &std::sync::mpsc::Sender<Events>,
-                       m: rosc::OscMessage) {
-
-        match (m.addr.as_ref(), m.args) {
-            ("/pole_leds", Some(ref args)) if args.len() == 1 => {
-                let arg = &args[0];
-                match *arg {
-                    rosc::OscType::Int(num) => {
-                        if num >= 0 {
-                            data.write().unwrap().num_leds_for_pole = num as usize;
-                        }
-                    }
-                    rosc::OscType::Float(num) => {
-                        if num >= 0.0 {
-                            data.write().unwrap().num_leds_for_pole = num as usize;
-                        }
-                    }
-                    _ => {
-                        warn!("got unexpect arg {:?}", *arg);
-                        return;
-                    }
-                }
+    pub fn get_pole_geometry(&self, pole: usize) -> PoleGeometry {
+        self.handle.get_pole_geometry(pole)
+    }
 
-                sender.send(Events::ConfigChanged);
-            }
+    pub fn get_mode(&self) -> Modes {
+        self.handle.get_mode()
+    }
 
-            ("/flower", Some(ref args)) if args.len() == 1 => {
-                let arg = &args[0];
-                let enabled = Self::to_bool(arg);
-                if let Some(enabled) = enabled {
-                    if enabled {
-                        sender.send(Events::ModeChanged(Modes::Flower));
-                    } else {
-                        sender.send(Events::ModeChanged(Modes::Regular));
-                    }
-                } else {
-                    warn!("got unexpect argument {:?}", *arg);
-                }
-            }
-            ("/mode/disco", _) => {
-                sender.send(Events::ModeChanged(Modes::Disco));
-            }
+    pub fn get_anim_params(&self) -> AnimParams {
+        self.handle.get_anim_params()
+    }
 
-            ("/mode/reg", _) => {
-                sender.send(Events::ModeChanged(Modes::Regular));
-            }
+    /// remember the active mode so it can be read back and saved
+    pub fn set_mode(&mut self, mode: Modes) {
+        self.handle.data.write().unwrap().mode = mode;
+    }
+}
 
-            ("/mode/flower", _) => {
-                sender.send(Events::ModeChanged(Modes::Flower));
-            }
-            ("/saveconfig", _) => {
-                sender.send(Events::SaveConfig);
-            }
-            _ => {warn!("got unexpected msg {:?}", m.addr);}
+/// json merge patch, rfc 7396
+fn merge(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    let patch = match *patch {
+        serde_json::Value::Object(ref patch) => patch,
+        _ => {
+            *target = patch.clone();
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(serde_json::Map::new());
+    }
+    let target = target.as_object_mut().unwrap();
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+            continue;
         }
+        if !target.contains_key(key) {
+            target.insert(key.clone(), serde_json::Value::Null);
+        }
+        merge(target.get_mut(key).unwrap(), value);
     }
+}
 
-    fn to_bool(t: &rosc::OscType) -> Option<bool> {
-        match *t {
-            rosc::OscType::Int(num) => Some(if num != 0 { true } else { false }),
-            rosc::OscType::Float(num) => Some(if num != 0.0 { true } else { false }),
-            rosc::OscType::Bool(b) => Some(b),
-            _ => None,
+/// apply to target what changed between old and new, and nothing else
+fn apply_changes(target: &mut serde_json::Value,
+                 old: &serde_json::Value,
+                 new: &serde_json::Value) {
+    if old == new {
+        return;
+    }
+    let (old, new) = match (old, new) {
+        (&serde_json::Value::Object(ref old), &serde_json::Value::Object(ref new))
+            if target.is_object() => (old, new),
+        _ => {
+            *target = new.clone();
+            return;
+        }
+    };
+    let null = serde_json::Value::Null;
+    let target = target.as_object_mut().unwrap();
+    for key in old.keys().chain(new.keys()) {
+        let value = match new.get(key) {
+            Some(value) => value,
+            None => {
+                target.remove(key);
+                continue;
+            }
+        };
+        if !target.contains_key(key) {
+            target.insert(key.clone(), serde_json::Value::Null);
         }
+        apply_changes(target.get_mut(key).unwrap(), old.get(key).unwrap_or(&null), value);
     }
 }
@@ -0,0 +1,342 @@
+use std;
+use std::net::UdpSocket;
+use rosc;
+
+use super::super::{Events, Modes};
+use super::{ConfigData, ConfigError, ConfigHandle};
+use status::StatusHandle;
+
+/// keys that /config/get accepts, in the order /config/dump sends them
+const CONFIG_KEYS: &[&str] = &["pole_leds", "cp1", "cp2", "heart", "mode", "poles", "anim",
+                               "presets"];
+
+/// where to send answers for queries received by the config server
+struct Reply<'a> {
+    socket: &'a UdpSocket,
+    addr: std::net::SocketAddr,
+}
+
+impl<'a> Reply<'a> {
+    fn send(&self, msgs: Vec<rosc::OscMessage>) {
+        for m in msgs {
+            let buf = match rosc::encoder::encode(&rosc::OscPacket::Message(m)) {
+                Ok(buf) => buf,
+                Err(e) => {
+                    warn!("can't encode reply {:?}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = self.socket.send_to(&buf, self.addr) {
+                warn!("can't send reply to {}: {}", self.addr, e);
+            }
+        }
+    }
+}
+
+pub fn start_config_server(config: ConfigHandle, status: StatusHandle, socket: UdpSocket) {
+    info!("osc config server up on {:?}", socket.local_addr());
+    let mut buf = [0; 4096];
+    loop {
+        let res = socket.recv_from(&mut buf);
+        if res.is_err() {
+            // TODO log
+            continue;
+        }
+        let (amt, src) = res.unwrap();
+        let buf = &mut buf[..amt];
+
+        let res = rosc::decoder::decode(&buf);
+        let msg = match res {
+            Err(_) => {
+
+            // TODO log
+            continue;
+            }
+            Ok(msg) => msg,
+        };
+
+        debug!("got osc packet {:?}", &msg);
+        let reply = Reply {
+            socket: &socket,
+            addr: src,
+        };
+        process(&config.with_source(&format!("osc {}", src)), &status, &reply, msg);
+    }
+
+}
+
+
+fn process(config: &ConfigHandle, status: &StatusHandle, reply: &Reply, p: rosc::OscPacket) {
+    match p {
+        rosc::OscPacket::Message(m) => {
+            process_message(config, status, reply, m);
+        }
+        rosc::OscPacket::Bundle(b) => {
+            // we ignore time tag. sorry.
+            for inner in b.content {
+                process(config, status, reply, inner);
+            }
+        }
+    }
+}
+
+fn process_message(config: &ConfigHandle,
+                   status: &StatusHandle,
+                   reply: &Reply,
+                   m: rosc::OscMessage) {
+
+    if let Some(res) = process_setter(config, &m.addr, m.args.as_ref()) {
+        if let Err(e) = res {
+            warn!("rejected config change {:?}: {}", m.addr, e);
+            reply.send(vec![msg("/config/error", vec![rosc::OscType::String(e.to_string())])]);
+        }
+        return;
+    }
+
+    match (m.addr.as_ref(), m.args) {
+        ("/flower", Some(ref args)) if args.len() == 1 => {
+            let arg = &args[0];
+            let enabled = to_bool(arg);
+            if let Some(enabled) = enabled {
+                if enabled {
+                    config.send(Events::ModeChanged(Modes::Flower));
+                } else {
+                    config.send(Events::ModeChanged(Modes::Regular));
+                }
+            } else {
+                warn!("got unexpect argument {:?}", *arg);
+            }
+        }
+        ("/mode/disco", _) => {
+            config.send(Events::ModeChanged(Modes::Disco));
+        }
+
+        ("/mode/reg", _) => {
+            config.send(Events::ModeChanged(Modes::Regular));
+        }
+
+        ("/mode/flower", _) => {
+            config.send(Events::ModeChanged(Modes::Flower));
+        }
+        ("/saveconfig", _) => {
+            config.send(Events::SaveConfig);
+        }
+        ("/brightness", Some(ref args)) if args.len() == 1 => {
+            match to_f32(&args[0]) {
+                Some(brightness) if brightness >= 0. && brightness <= 1. => {
+                    config.send(Events::BrightnessChanged(brightness));
+                }
+                _ => warn!("got unexpect argument {:?}", args[0]),
+            }
+        }
+        ("/config/get", Some(ref args)) if args.len() == 1 => {
+            let key = match args[0] {
+                rosc::OscType::String(ref key) => key.clone(),
+                _ => {
+                    warn!("got unexpect argument {:?}", args[0]);
+                    return;
+                }
+            };
+            match get_values(&config.data.read().unwrap(), &key) {
+                Some(msgs) => reply.send(msgs),
+                None => warn!("got request for unknown config key {}", key),
+            }
+        }
+        ("/status/offline", _) => {
+            let offline = status.get()
+                .offline
+                .iter()
+                .map(|p| rosc::OscType::Int(p as i32))
+                .collect();
+            reply.send(vec![msg("/status/offline", offline)]);
+        }
+        // revisions that /config/revert accepts: id, age in seconds, who replaced it
+        ("/config/history", _) => {
+            let now = std::time::SystemTime::now();
+            let msgs = config.get_history()
+                .into_iter()
+                .map(|r| {
+                    let age = now.duration_since(r.time).map(|d| d.as_secs()).unwrap_or(0);
+                    msg("/config/revision",
+                        vec![rosc::OscType::Int(r.id as i32),
+                             rosc::OscType::Int(age as i32),
+                             rosc::OscType::String(r.source)])
+                })
+                .collect();
+            reply.send(msgs);
+        }
+        ("/config/dump", _) => {
+            let data = config.data.read().unwrap();
+            let msgs = CONFIG_KEYS.iter()
+                .filter_map(|key| get_values(&data, key))
+                .flat_map(|msgs| msgs.into_iter())
+                .collect();
+            reply.send(msgs);
+        }
+        _ => {warn!("got unexpected msg {:?}", m.addr);}
+    }
+}
+
+/// config changes. returns None if addr is not a setter.
+pub fn process_setter(config: &ConfigHandle,
+                      addr: &str,
+                      args: Option<&Vec<rosc::OscType>>)
+                      -> Option<Result<(), ConfigError>> {
+    let args = args.map_or(&[][..], |args| &args[..]);
+    let res = match (addr, args.len()) {
+        ("/pole_leds", 1) => update(config, args, |d, v| d.num_leds_for_pole = v[0]),
+        ("/cp1", 1) => update(config, args, |d, v| d.cp1 = v[0]),
+        ("/cp2", 1) => update(config, args, |d, v| d.cp2 = v[0]),
+        ("/heart", 2) => update(config, args, |d, v| d.heart = v[0]..v[1]),
+        ("/heart/start", 1) => update(config, args, |d, v| d.heart.start = v[0]),
+        ("/heart/end", 1) => update(config, args, |d, v| d.heart.end = v[0]),
+        ("/anim/heart", 1) => update_f32(config, args, |d, v| d.anim.heart_period = v[0]),
+        ("/anim/flower", 1) => update_f32(config, args, |d, v| d.anim.flower_period = v[0]),
+        ("/anim/disco", 1) => update_f32(config, args, |d, v| d.anim.disco_period = v[0]),
+        ("/preset/save", 1) => to_string(&args[0]).and_then(|name| config.save_preset(&name)),
+        ("/preset/load", 1) => to_string(&args[0]).and_then(|name| config.load_preset(&name)),
+        ("/config/undo", 0) => config.undo(),
+        ("/config/revert", 1) => {
+            to_usize(&args[0])
+                .ok_or_else(|| ConfigError::InvalidArgs(format!("{:?}", args)))
+                .and_then(|id| config.revert(id))
+        }
+        (addr, _) if addr.starts_with("/pole/") => process_pole_setter(config, addr, args),
+        _ => return None,
+    };
+    Some(res)
+}
+
+/// per pole overrides: /pole/<index>/<key> <values..>
+fn process_pole_setter(config: &ConfigHandle,
+                       addr: &str,
+                       args: &[rosc::OscType])
+                       -> Result<(), ConfigError> {
+    let parts: Vec<&str> = addr.split('/').collect();
+    if parts.len() != 4 {
+        return Err(ConfigError::InvalidArgs(addr.to_string()));
+    }
+    let pole = match parts[2].parse::<usize>() {
+        Ok(pole) if pole < config.get_installation().num_poles => pole,
+        Ok(pole) => return Err(ConfigError::UnknownPole(pole)),
+        Err(_) => return Err(ConfigError::InvalidArgs(addr.to_string())),
+    };
+
+    match (parts[3], args.len()) {
+        ("pole_leds", 1) => {
+            update(config, args, |d, v| d.pole_override(pole).num_leds_for_pole = Some(v[0]))
+        }
+        ("cp1", 1) => update(config, args, |d, v| d.pole_override(pole).cp1 = Some(v[0])),
+        ("cp2", 1) => update(config, args, |d, v| d.pole_override(pole).cp2 = Some(v[0])),
+        ("heart", 2) => {
+            update(config, args, |d, v| d.pole_override(pole).heart = Some((v[0], v[1])))
+        }
+        // back to the global values
+        ("reset", _) => update(config, &[], |d, _| d.remove_pole_override(pole)),
+        _ => Err(ConfigError::InvalidArgs(format!("{} {:?}", addr, args))),
+    }
+}
+
+/// convert the osc args to numbers and apply them with f.
+fn update<F>(config: &ConfigHandle, args: &[rosc::OscType], f: F) -> Result<(), ConfigError>
+    where F: FnOnce(&mut ConfigData, &[usize])
+{
+    let values: Option<Vec<usize>> = args.iter().map(to_usize).collect();
+    let values = match values {
+        Some(values) => values,
+        None => return Err(ConfigError::InvalidArgs(format!("{:?}", args))),
+    };
+
+    config.change(|d| f(d, &values))
+}
+
+fn update_f32<F>(config: &ConfigHandle, args: &[rosc::OscType], f: F) -> Result<(), ConfigError>
+    where F: FnOnce(&mut ConfigData, &[f32])
+{
+    let values: Option<Vec<f32>> = args.iter().map(to_f32).collect();
+    let values = match values {
+        Some(values) => values,
+        None => return Err(ConfigError::InvalidArgs(format!("{:?}", args))),
+    };
+
+    config.change(|d| f(d, &values))
+}
+
+/// the current value of a config key, as messages that can be sent back to
+/// a control surface. setters and replies use the same address.
+fn get_values(data: &ConfigData, key: &str) -> Option<Vec<rosc::OscMessage>> {
+    let int = |n: usize| rosc::OscType::Int(n as i32);
+    let float = |n: f32| rosc::OscType::Float(n);
+    let msgs = match key {
+        "pole_leds" => vec![msg("/pole_leds", vec![int(data.num_leds_for_pole)])],
+        "cp1" => vec![msg("/cp1", vec![int(data.cp1)])],
+        "cp2" => vec![msg("/cp2", vec![int(data.cp2)])],
+        "heart" => vec![msg("/heart", vec![int(data.heart.start), int(data.heart.end)])],
+        "mode" => vec![msg("/mode", vec![rosc::OscType::String(data.mode.name().to_string())])],
+        // geometry of each pole: index, length, cp1, cp2, heart start, heart end
+        "poles" => {
+            (0..data.installation.num_poles)
+                .map(|i| {
+                    let g = data.pole_geometry(i);
+                    msg("/config/pole",
+                        vec![int(i),
+                             int(g.num_leds_for_pole),
+                             int(g.cp1),
+                             int(g.cp2),
+                             int(g.heart.start),
+                             int(g.heart.end)])
+                })
+                .collect()
+        }
+        "anim" => {
+            vec![msg("/anim/heart", vec![float(data.anim.heart_period)]),
+                 msg("/anim/flower", vec![float(data.anim.flower_period)]),
+                 msg("/anim/disco", vec![float(data.anim.disco_period)])]
+        }
+        "presets" => {
+            vec![msg("/presets",
+                     data.presets.keys().map(|name| rosc::OscType::String(name.clone())).collect())]
+        }
+        _ => return None,
+    };
+    Some(msgs)
+}
+
+fn msg(addr: &str, args: Vec<rosc::OscType>) -> rosc::OscMessage {
+    rosc::OscMessage {
+        addr: addr.to_string(),
+        args: Some(args),
+    }
+}
+
+fn to_usize(t: &rosc::OscType) -> Option<usize> {
+    match *t {
+        rosc::OscType::Int(num) if num >= 0 => Some(num as usize),
+        rosc::OscType::Float(num) if num >= 0.0 => Some(num as usize),
+        _ => None,
+    }
+}
+
+fn to_f32(t: &rosc::OscType) -> Option<f32> {
+    match *t {
+        rosc::OscType::Int(num) => Some(num as f32),
+        rosc::OscType::Float(num) => Some(num),
+        _ => None,
+    }
+}
+
+fn to_string(t: &rosc::OscType) -> Result<String, ConfigError> {
+    match *t {
+        rosc::OscType::String(ref s) => Ok(s.clone()),
+        _ => Err(ConfigError::InvalidArgs(format!("{:?}", t))),
+    }
+}
+
+fn to_bool(t: &rosc::OscType) -> Option<bool> {
+    match *t {
+        rosc::OscType::Int(num) => Some(if num != 0 { true } else { false }),
+        rosc::OscType::Float(num) => Some(if num != 0.0 { true } else { false }),
+        rosc::OscType::Bool(b) => Some(b),
+        _ => None,
+    }
+}
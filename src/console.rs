@@ -0,0 +1,274 @@
+use std;
+use std::io::{BufRead, Write};
+
+use super::{EventTypes, Events, Modes};
+use config;
+use status;
+
+/// scripts can source scripts, up to this deep
+const MAX_SOURCE_DEPTH: usize = 8;
+
+const HELP: &str = "\
+touch <pole> [for <secs>]   touch a pole, and let go after secs if given
+connect <pole> <pole>...    hold hands: connect each pole to the next
+release [<pole>...]         let go of the touches made here, or only those on the poles
+reset                       let go of all touches, from everywhere
+mode <name>                 regular, disco or flower
+preset <name>               load a preset
+set <key> [<value>...]      change the config, keys as the osc setters: cp1, heart, pole/3/reset...
+status                      mode, offline poles and the touches made here
+source <file>               run the commands in a file, on stdin only
+help                        this
+";
+
+/// a line based command language to poke the installation, for stdin and the
+/// control socket.
+pub struct Console {
+    config: config::ConfigHandle,
+    status: status::StatusHandle,
+    sender: std::sync::mpsc::Sender<Events>,
+    /// connections started from this console and not released yet
+    touching: std::collections::BTreeSet<(usize, usize)>,
+    /// the timer of each pole touched for a while. a timer only lets go if
+    /// it is still the pole's, so touching the pole again cancels it
+    timers: std::sync::Arc<std::sync::Mutex<std::collections::BTreeMap<usize, u64>>>,
+    next_timer: u64,
+    /// whether source may read files. not for remote consoles, whose errors
+    /// would show the files line by line
+    can_source: bool,
+    depth: usize,
+}
+
+impl Console {
+    pub fn new(config: config::ConfigHandle,
+               status: status::StatusHandle,
+               sender: std::sync::mpsc::Sender<Events>,
+               can_source: bool)
+               -> Self {
+        Console {
+            config: config,
+            status: status,
+            sender: sender,
+            touching: std::collections::BTreeSet::new(),
+            timers: std::sync::Arc::new(std::sync::Mutex::new(std::collections::BTreeMap::new())),
+            next_timer: 0,
+            can_source: can_source,
+            depth: 0,
+        }
+    }
+
+    /// run the commands from input until it ends
+    pub fn serve<R: BufRead, W: Write>(&mut self, input: R, out: &mut W) -> std::io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            self.run(&line, out)?;
+        }
+        Ok(())
+    }
+
+    /// run one command. errors are reported to out.
+    pub fn run<W: Write>(&mut self, line: &str, out: &mut W) -> std::io::Result<()> {
+        if let Err(e) = self.execute(line, out) {
+            writeln!(out, "error: {}", e)?;
+        }
+        out.flush()
+    }
+
+    fn execute<W: Write>(&mut self, line: &str, out: &mut W) -> Result<(), String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = (words[0], &words[1..]);
+
+        match command {
+            "touch" => {
+                let (pole, secs) = match args.len() {
+                    1 => (self.pole(args[0])?, None),
+                    3 if args[1] == "for" => (self.pole(args[0])?, Some(secs(args[2])?)),
+                    _ => return Err("usage: touch <pole> [for <secs>]".to_string()),
+                };
+                self.start(pole, pole);
+                if let Some(secs) = secs {
+                    self.release_after(pole, secs);
+                }
+            }
+            "connect" => {
+                if args.len() < 2 {
+                    return Err("usage: connect <pole> <pole>...".to_string());
+                }
+                let poles = self.poles(args)?;
+                for pair in poles.windows(2) {
+                    self.start(pair[0], pair[1]);
+                }
+            }
+            "release" => {
+                let poles = self.poles(args)?;
+                let released: Vec<(usize, usize)> = self.touching
+                    .iter()
+                    .filter(|&&(p1, p2)| {
+                        poles.is_empty() || poles.contains(&p1) || poles.contains(&p2)
+                    })
+                    .cloned()
+                    .collect();
+                for (p1, p2) in released {
+                    self.stop(p1, p2);
+                }
+                // and those still waiting for their timer
+                let timed: Vec<usize> = {
+                    let mut timers = self.timers.lock().unwrap();
+                    let timed: Vec<usize> = timers.keys()
+                        .filter(|&&p| poles.is_empty() || poles.contains(&p))
+                        .cloned()
+                        .collect();
+                    for pole in timed.iter() {
+                        timers.remove(pole);
+                    }
+                    timed
+                };
+                for pole in timed {
+                    self.sender.send(Events::Stop(EventTypes::Connect(pole, pole)));
+                }
+            }
+            "reset" => {
+                self.touching.clear();
+                self.timers.lock().unwrap().clear();
+                self.sender.send(Events::Reset);
+            }
+            "mode" if args.len() == 1 => {
+                match Modes::from_name(args[0]) {
+                    Some(mode) => {
+                        self.sender.send(Events::ModeChanged(mode));
+                    }
+                    None => {
+                        return Err(format!("unknown mode {:?}, try regular, disco or flower",
+                                           args[0]))
+                    }
+                }
+            }
+            "preset" if args.len() == 1 => {
+                self.config.load_preset(args[0]).map_err(|e| e.to_string())?;
+                writeln!(out, "loaded preset {}", args[0]).map_err(|e| e.to_string())?;
+            }
+            // some keys take no value, like pole/3/reset
+            "set" if !args.is_empty() => {
+                self.config.set(args[0], &args[1..]).map_err(|e| e.to_string())?;
+            }
+            "status" => self.print_status(out).map_err(|e| e.to_string())?,
+            "source" if args.len() == 1 => self.source(args[0], out)?,
+            "help" => out.write_all(HELP.as_bytes()).map_err(|e| e.to_string())?,
+            "mode" | "preset" | "set" | "source" => {
+                return Err(format!("wrong arguments for {}, see help", command))
+            }
+            // the old shortcuts: "<pole>", "<pole> <pole>", "u <pole> [<pole>]" and the mode names
+            "u" if args.len() == 1 || args.len() == 2 => {
+                let poles = self.poles(args)?;
+                self.stop(poles[0], *poles.last().unwrap());
+            }
+            _ if words.len() <= 2 && words.iter().all(|w| w.parse::<usize>().is_ok()) => {
+                let poles = self.poles(&words)?;
+                self.start(poles[0], *poles.last().unwrap());
+            }
+            _ if words.len() == 1 && Modes::from_name(command).is_some() => {
+                self.sender.send(Events::ModeChanged(Modes::from_name(command).unwrap()));
+            }
+            _ => return Err(format!("unknown command {:?}, try help", command)),
+        }
+        Ok(())
+    }
+
+    fn start(&mut self, pole1: usize, pole2: usize) {
+        if pole1 == pole2 {
+            self.timers.lock().unwrap().remove(&pole1);
+        }
+        self.touching.insert((pole1, pole2));
+        self.sender.send(Events::Start(EventTypes::Connect(pole1, pole2)));
+    }
+
+    fn stop(&mut self, pole1: usize, pole2: usize) {
+        self.touching.remove(&(pole1, pole2));
+        self.sender.send(Events::Stop(EventTypes::Connect(pole1, pole2)));
+    }
+
+    fn release_after(&mut self, pole: usize, secs: std::time::Duration) {
+        // forget it now, the timer lets go
+        self.touching.remove(&(pole, pole));
+        self.next_timer += 1;
+        let timer = self.next_timer;
+        self.timers.lock().unwrap().insert(pole, timer);
+        let timers = self.timers.clone();
+        let sender = self.sender.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(secs);
+            let mut timers = timers.lock().unwrap();
+            if timers.get(&pole) == Some(&timer) {
+                timers.remove(&pole);
+                sender.send(Events::Stop(EventTypes::Connect(pole, pole)));
+            }
+        });
+    }
+
+    fn pole(&self, word: &str) -> Result<usize, String> {
+        let num_poles = self.config.get_installation().num_poles;
+        match word.parse::<usize>() {
+            Ok(pole) if pole < num_poles => Ok(pole),
+            Ok(pole) => {
+                Err(format!("there is no pole {}, they go from 0 to {}", pole, num_poles - 1))
+            }
+            Err(_) => Err(format!("{:?} is not a pole number", word)),
+        }
+    }
+
+    fn poles(&self, words: &[&str]) -> Result<Vec<usize>, String> {
+        words.iter().map(|w| self.pole(w)).collect()
+    }
+
+    fn print_status<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        let status = self.status.get();
+        writeln!(out, "mode {}", self.config.get_mode().name())?;
+        let offline: Vec<String> = status.offline.iter().map(|p| p.to_string()).collect();
+        writeln!(out, "offline poles: {}", offline.join(" "))?;
+        let touching: Vec<String> = self.touching
+            .iter()
+            .map(|&(p1, p2)| if p1 == p2 { p1.to_string() } else { format!("{}-{}", p1, p2) })
+            .collect();
+        writeln!(out, "touching here: {}", touching.join(" "))
+    }
+
+    fn source<W: Write>(&mut self, path: &str, out: &mut W) -> Result<(), String> {
+        if !self.can_source {
+            return Err("source only works on the stdin console".to_string());
+        }
+        if self.depth >= MAX_SOURCE_DEPTH {
+            return Err(format!("not sourcing {}, scripts are nested too deep", path));
+        }
+        let file = std::fs::File::open(path).map_err(|e| format!("can't open {}: {}", path, e))?;
+
+        self.depth += 1;
+        let mut res = Ok(());
+        for (n, line) in std::io::BufReader::new(file).lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    res = Err(format!("{}: {}", path, e));
+                    break;
+                }
+            };
+            // stop at the first error, later lines may depend on it
+            if let Err(e) = self.execute(&line, out) {
+                res = Err(format!("{}:{}: {}", path, n + 1, e));
+                break;
+            }
+        }
+        self.depth -= 1;
+        res
+    }
+}
+
+fn secs(word: &str) -> Result<std::time::Duration, String> {
+    match word.parse::<f32>() {
+        Ok(secs) if secs >= 0. => Ok(std::time::Duration::from_millis((secs * 1000.) as u64)),
+        _ => Err(format!("{:?} is not a number of seconds", word)),
+    }
+}
@@ -0,0 +1,99 @@
+use std;
+use std::io::{Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+use std::net::TcpListener;
+
+use super::Events;
+use config;
+use console::Console;
+use status;
+
+/// the console on a socket, for when stdin is not ours, e.g. under systemd.
+/// addr is "unix:<path>" for a unix socket, or a tcp address like 127.0.0.1:8135.
+/// every client gets a console of its own.
+pub fn start(addr: &str,
+             config: config::ConfigHandle,
+             status: status::StatusHandle,
+             sender: std::sync::mpsc::Sender<Events>)
+             -> std::io::Result<()> {
+    if addr.starts_with("unix:") {
+        let path = &addr["unix:".len()..];
+        // a socket left over from the last run would fail the bind. anything
+        // else at the path is not ours to delete
+        match std::fs::symlink_metadata(path) {
+            Ok(ref meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+            Ok(_) => {
+                return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists,
+                                               format!("{} exists and is not a socket", path)))
+            }
+            Err(_) => {}
+        }
+        let listener = UnixListener::bind(path)?;
+        info!("control socket listening on {}", path);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("can't accept a control client: {}", e);
+                        continue;
+                    }
+                };
+                match stream.try_clone() {
+                    Ok(reader) => {
+                        let console = Console::new(config.with_source("control socket"),
+                                                   status.clone(),
+                                                   sender.clone(),
+                                                   false);
+                        serve(reader, stream, "unix socket".to_string(), console);
+                    }
+                    Err(e) => warn!("can't serve a control client: {}", e),
+                }
+            }
+        });
+    } else {
+        let listener = TcpListener::bind(addr)?;
+        info!("control socket listening on {}", addr);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("can't accept a control client: {}", e);
+                        continue;
+                    }
+                };
+                let peer = stream.peer_addr()
+                    .map(|a| a.to_string())
+                    .unwrap_or_else(|_| "unknown".to_string());
+                match stream.try_clone() {
+                    Ok(reader) => {
+                        let console = Console::new(config.with_source(&format!("control {}", peer)),
+                                                   status.clone(),
+                                                   sender.clone(),
+                                                   false);
+                        serve(reader, stream, peer, console);
+                    }
+                    Err(e) => warn!("can't serve control client {}: {}", peer, e),
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+/// run the client's commands on a thread of its own, until it hangs up.
+/// its touches stay, like those typed on stdin.
+fn serve<S>(reader: S, mut writer: S, peer: String, mut console: Console)
+    where S: Read + Write + Send + 'static
+{
+    std::thread::spawn(move || {
+        info!("control client {} connected", peer);
+        let input = std::io::BufReader::new(reader);
+        if let Err(e) = console.serve(input, &mut writer) {
+            warn!("control client {}: {}", peer, e);
+        }
+        info!("control client {} left", peer);
+    });
+}
@@ -0,0 +1,251 @@
+use std;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use super::super::{EventTypes, Events};
+use config;
+
+/// pass the events sent to the returned sender on to sender, with touch flicker
+/// filtered out as set in the debounce config. everything but touches passes right away.
+pub fn debounce(config: config::ConfigHandle, sender: Sender<Events>) -> Sender<Events> {
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || Debouncer::new(config, sender).run(rx));
+    tx
+}
+
+/// a change that waits for its delay before it is passed on
+#[derive(Clone,Copy,Debug)]
+enum Pending {
+    Start(Instant),
+    Stop(Instant),
+}
+
+impl Pending {
+    fn due(&self) -> Instant {
+        match *self {
+            Pending::Start(due) | Pending::Stop(due) => due,
+        }
+    }
+}
+
+struct Debouncer {
+    config: config::ConfigHandle,
+    sender: Sender<Events>,
+    /// connections whose start was passed on, and not their stop yet
+    active: BTreeSet<(usize, usize)>,
+    pending: BTreeMap<(usize, usize), Pending>,
+}
+
+impl Debouncer {
+    fn new(config: config::ConfigHandle, sender: Sender<Events>) -> Self {
+        Debouncer {
+            config: config,
+            sender: sender,
+            active: BTreeSet::new(),
+            pending: BTreeMap::new(),
+        }
+    }
+
+    fn run(&mut self, receiver: Receiver<Events>) {
+        loop {
+            let next_due = self.pending.values().map(|p| p.due()).min();
+            let event = match next_due {
+                Some(due) => {
+                    let now = Instant::now();
+                    let wait = if due > now { due - now } else { Duration::from_secs(0) };
+                    match receiver.recv_timeout(wait) {
+                        Ok(event) => Some(event),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+                None => {
+                    match receiver.recv() {
+                        Ok(event) => Some(event),
+                        Err(_) => return,
+                    }
+                }
+            };
+
+            if let Some(event) = event {
+                self.process(event, Instant::now());
+            }
+            self.send_due(Instant::now());
+        }
+    }
+
+    fn process(&mut self, event: Events, now: Instant) {
+        match event {
+            Events::Start(EventTypes::Connect(pole1, pole2)) => {
+                let key = (pole1.min(pole2), pole1.max(pole2));
+                match self.pending.get(&key).cloned() {
+                    // touched again before the release delay ran out
+                    Some(Pending::Stop(_)) => {
+                        self.pending.remove(&key);
+                    }
+                    Some(Pending::Start(_)) => {}
+                    None if self.active.contains(&key) => {}
+                    None => {
+                        let (hold, _) = self.config.get_sensors().debounce.for_poles(pole1, pole2);
+                        self.pending.insert(key, Pending::Start(now + secs(hold)));
+                    }
+                }
+            }
+            Events::Stop(EventTypes::Connect(pole1, pole2)) => {
+                let key = (pole1.min(pole2), pole1.max(pole2));
+                match self.pending.get(&key).cloned() {
+                    // let go before the hold time ran out
+                    Some(Pending::Start(_)) => {
+                        self.pending.remove(&key);
+                    }
+                    Some(Pending::Stop(_)) => {}
+                    None if !self.active.contains(&key) => {}
+                    None => {
+                        let (_, release) = self.config
+                            .get_sensors()
+                            .debounce
+                            .for_poles(pole1, pole2);
+                        self.pending.insert(key, Pending::Stop(now + secs(release)));
+                    }
+                }
+            }
+            Events::Reset => {
+                self.active.clear();
+                self.pending.clear();
+                self.sender.send(Events::Reset);
+            }
+            event => {
+                self.sender.send(event);
+            }
+        }
+    }
+
+    fn send_due(&mut self, now: Instant) {
+        let due: Vec<((usize, usize), Pending)> = self.pending
+            .iter()
+            .filter(|&(_, p)| p.due() <= now)
+            .map(|(k, p)| (*k, *p))
+            .collect();
+
+        for (key, pending) in due {
+            self.pending.remove(&key);
+            let (pole1, pole2) = key;
+            match pending {
+                Pending::Start(_) => {
+                    self.active.insert(key);
+                    self.sender.send(Events::Start(EventTypes::Connect(pole1, pole2)));
+                }
+                Pending::Stop(_) => {
+                    self.active.remove(&key);
+                    self.sender.send(Events::Stop(EventTypes::Connect(pole1, pole2)));
+                }
+            }
+        }
+    }
+}
+
+fn secs(secs: f32) -> Duration {
+    Duration::from_millis((secs * 1000.) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    /// a debouncer with the given debounce config, and where it sends to
+    fn debouncer(debounce: &str) -> (Debouncer, Receiver<Events>) {
+        let (tx, _) = std::sync::mpsc::channel();
+        let config = config::Config::new(std::path::Path::new("/nonexistent/config.json"),
+                                         config::Installation::new(),
+                                         tx,
+                                         None);
+        let patch = format!(r#"{{"sensors": {{"debounce": {}}}}}"#, debounce);
+        config.handle().patch(&serde_json::from_str(&patch).unwrap()).unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        (Debouncer::new(config.handle(), tx), rx)
+    }
+
+    fn sent(rx: &Receiver<Events>) -> String {
+        let events: Vec<Events> = rx.try_iter().collect();
+        format!("{:?}", events)
+    }
+
+    fn start(pole1: usize, pole2: usize) -> Events {
+        Events::Start(EventTypes::Connect(pole1, pole2))
+    }
+
+    fn stop(pole1: usize, pole2: usize) -> Events {
+        Events::Stop(EventTypes::Connect(pole1, pole2))
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn hold_delays_the_start() {
+        let (mut d, rx) = debouncer(r#"{"hold": 0.1}"#);
+        let t = Instant::now();
+        d.process(start(2, 1), t);
+        d.send_due(t + ms(99));
+        assert_eq!(sent(&rx), "[]");
+        d.send_due(t + ms(100));
+        assert_eq!(sent(&rx), "[Start(Connect(1, 2))]");
+    }
+
+    #[test]
+    fn release_delays_the_stop() {
+        let (mut d, rx) = debouncer(r#"{"release": 0.3}"#);
+        let t = Instant::now();
+        d.process(start(1, 2), t);
+        d.send_due(t);
+        assert_eq!(sent(&rx), "[Start(Connect(1, 2))]");
+        d.process(stop(1, 2), t + ms(10));
+        d.send_due(t + ms(309));
+        assert_eq!(sent(&rx), "[]");
+        d.send_due(t + ms(310));
+        assert_eq!(sent(&rx), "[Stop(Connect(1, 2))]");
+    }
+
+    #[test]
+    fn flicker_is_cancelled() {
+        let (mut d, rx) = debouncer(r#"{"hold": 0.1, "release": 0.3}"#);
+        let t = Instant::now();
+        // let go before the hold ran out
+        d.process(start(1, 2), t);
+        d.process(stop(1, 2), t + ms(50));
+        d.send_due(t + ms(500));
+        assert_eq!(sent(&rx), "[]");
+
+        // touched again before the release ran out
+        d.process(start(1, 2), t + ms(1000));
+        d.send_due(t + ms(1100));
+        assert_eq!(sent(&rx), "[Start(Connect(1, 2))]");
+        d.process(stop(1, 2), t + ms(1200));
+        d.process(start(1, 2), t + ms(1300));
+        d.send_due(t + ms(2000));
+        assert_eq!(sent(&rx), "[]");
+    }
+
+    #[test]
+    fn poles_override_the_delays() {
+        let (mut d, rx) = debouncer(r#"{"release": 0.1, "poles": [{"pole": 7, "release": 0.6}]}"#);
+        let t = Instant::now();
+        for &(pole1, pole2) in &[(3, 4), (3, 7)] {
+            d.process(start(pole1, pole2), t);
+        }
+        d.send_due(t);
+        assert_eq!(sent(&rx), "[Start(Connect(3, 4)), Start(Connect(3, 7))]");
+        for &(pole1, pole2) in &[(3, 4), (3, 7)] {
+            d.process(stop(pole1, pole2), t);
+        }
+        d.send_due(t + ms(100));
+        assert_eq!(sent(&rx), "[Stop(Connect(3, 4))]");
+        d.send_due(t + ms(599));
+        assert_eq!(sent(&rx), "[]");
+        d.send_due(t + ms(600));
+        assert_eq!(sent(&rx), "[Stop(Connect(3, 7))]");
+    }
+}
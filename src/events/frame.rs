@@ -0,0 +1,250 @@
+use std;
+
+// the touch controller sends one line per pole. two formats are accepted,
+// told apart by the first character:
+//
+// v1: <lastheardof ms>:<sender>:<touch>:<touch>...
+// v2: @2:<seq>:<lastheardof ms>:<sender>:<touch>...*<crc>
+//
+// in v2, seq counts lines from 0 to 65535 and wraps, and crc is the
+// CRC-16/CCITT-FALSE of everything between '@' and '*', as 4 hex digits.
+
+/// one line from the touch controller
+#[derive(Clone,Debug,PartialEq)]
+pub struct Reading {
+    /// how long ago the controller heard from the sender
+    pub lastheardof: std::time::Duration,
+    pub sender: usize,
+    pub touching: Vec<usize>,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum FrameError {
+    Malformed(String),
+    UnknownVersion(String),
+    BadCrc { expected: u16, actual: u16 },
+    /// the same sequence number twice in a row
+    Duplicate(u16),
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            FrameError::Malformed(ref line) => write!(f, "malformed line {:?}", line),
+            FrameError::UnknownVersion(ref version) => {
+                write!(f, "unknown protocol version {:?}", version)
+            }
+            FrameError::BadCrc { expected, actual } => {
+                write!(f, "bad crc {:04x}, expected {:04x}", actual, expected)
+            }
+            FrameError::Duplicate(seq) => write!(f, "duplicate frame {}", seq),
+        }
+    }
+}
+
+/// counts of what went wrong since the port was opened
+#[derive(Clone,Copy,Debug,Default)]
+pub struct FrameStats {
+    pub bad_crc: usize,
+    pub malformed: usize,
+    /// v2 frames that never arrived, from gaps in the sequence numbers
+    pub missed: usize,
+}
+
+pub struct Parser {
+    last_seq: Option<u16>,
+    pub stats: FrameStats,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Parser {
+            last_seq: None,
+            stats: FrameStats::default(),
+        }
+    }
+
+    /// a line that isn't even text, as a glitching uart sends
+    pub fn not_text(&mut self, line: &[u8]) -> FrameError {
+        self.stats.malformed += 1;
+        FrameError::Malformed(String::from_utf8_lossy(line).into_owned())
+    }
+
+    pub fn parse(&mut self, line: &str) -> Result<Reading, FrameError> {
+        let res = if line.starts_with('@') {
+            self.parse_v2(&line[1..])
+        } else {
+            parse_fields(line)
+        };
+        match res {
+            Err(FrameError::BadCrc { .. }) => self.stats.bad_crc += 1,
+            Err(FrameError::Malformed(_)) |
+            Err(FrameError::UnknownVersion(_)) => self.stats.malformed += 1,
+            _ => {}
+        }
+        res
+    }
+
+    fn parse_v2(&mut self, frame: &str) -> Result<Reading, FrameError> {
+        let malformed = || FrameError::Malformed(format!("@{}", frame));
+
+        let star = frame.rfind('*').ok_or_else(&malformed)?;
+        let (body, crc) = (&frame[..star], &frame[star + 1..]);
+        if crc.len() != 4 || !crc.chars().all(|c| c.is_digit(16)) {
+            return Err(malformed());
+        }
+        let actual = u16::from_str_radix(crc, 16).map_err(|_| malformed())?;
+        let expected = crc16(body.as_bytes());
+        if actual != expected {
+            return Err(FrameError::BadCrc {
+                expected: expected,
+                actual: actual,
+            });
+        }
+
+        let mut parts = body.splitn(3, ':');
+        match parts.next() {
+            Some("2") => {}
+            Some(version) => return Err(FrameError::UnknownVersion(version.to_string())),
+            None => return Err(malformed()),
+        }
+        let seq = parts.next().and_then(|s| s.parse::<u16>().ok()).ok_or_else(&malformed)?;
+        let reading = parse_fields(parts.next().ok_or_else(&malformed)?)?;
+
+        if let Some(last) = self.last_seq {
+            if seq == last {
+                return Err(FrameError::Duplicate(seq));
+            }
+            self.stats.missed += seq.wrapping_sub(last).wrapping_sub(1) as usize;
+        }
+        self.last_seq = Some(seq);
+        Ok(reading)
+    }
+}
+
+/// <lastheardof ms>:<sender>:<touch>...
+fn parse_fields(line: &str) -> Result<Reading, FrameError> {
+    let v: Result<Vec<usize>, _> = line.split(':').map(|s| s.parse::<usize>()).collect();
+    match v {
+        Ok(ref v) if v.len() >= 2 => {
+            Ok(Reading {
+                lastheardof: std::time::Duration::from_millis(v[0] as u64),
+                sender: v[1],
+                touching: v[2..].to_vec(),
+            })
+        }
+        _ => Err(FrameError::Malformed(line.to_string())),
+    }
+}
+
+/// CRC-16/CCITT-FALSE: poly 0x1021, init 0xffff, no reflection
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2(body: &str) -> String {
+        format!("@{}*{:04x}", body, crc16(body.as_bytes()))
+    }
+
+    fn reading(ms: u64, sender: usize, touching: Vec<usize>) -> Reading {
+        Reading {
+            lastheardof: std::time::Duration::from_millis(ms),
+            sender: sender,
+            touching: touching,
+        }
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    fn good_v2_frame() {
+        let mut parser = Parser::new();
+        assert_eq!(parser.parse(&v2("2:7:120:3:4:5")), Ok(reading(120, 3, vec![4, 5])));
+        assert_eq!(parser.parse(&v2("2:8:0:3").to_uppercase()), Ok(reading(0, 3, vec![])));
+        assert_eq!(parser.stats.missed, 0);
+    }
+
+    #[test]
+    fn bad_crc() {
+        let mut parser = Parser::new();
+        let crc = crc16(b"2:7:120:3:4:5");
+        let line = format!("@2:7:120:3:4:5*{:04x}", crc ^ 1);
+        assert_eq!(parser.parse(&line),
+                   Err(FrameError::BadCrc {
+                       expected: crc,
+                       actual: crc ^ 1,
+                   }));
+        assert_eq!(parser.stats.bad_crc, 1);
+    }
+
+    #[test]
+    fn crc_not_4_hex_digits() {
+        let mut parser = Parser::new();
+        let crc = crc16(b"2:7:120:3");
+        for line in &[format!("@2:7:120:3*{:x}", crc & 0xfff),
+                      format!("@2:7:120:3*0{:04x}", crc),
+                      "@2:7:120:3*zzzz".to_string(),
+                      format!("@2:7:120:3*+{:03x}", crc & 0xfff),
+                      "@2:7:120:3".to_string()] {
+            match parser.parse(line) {
+                Err(FrameError::Malformed(_)) => {}
+                res => panic!("{:?} parsed as {:?}", line, res),
+            }
+        }
+        assert_eq!(parser.stats.malformed, 5);
+        assert_eq!(parser.stats.bad_crc, 0);
+    }
+
+    #[test]
+    fn duplicate_seq() {
+        let mut parser = Parser::new();
+        assert!(parser.parse(&v2("2:5:0:1")).is_ok());
+        assert_eq!(parser.parse(&v2("2:5:0:1")), Err(FrameError::Duplicate(5)));
+        assert!(parser.parse(&v2("2:6:0:1")).is_ok());
+        assert_eq!(parser.stats.missed, 0);
+    }
+
+    #[test]
+    fn seq_wraps() {
+        let mut parser = Parser::new();
+        assert!(parser.parse(&v2("2:65534:0:1")).is_ok());
+        assert!(parser.parse(&v2("2:65535:0:1")).is_ok());
+        assert!(parser.parse(&v2("2:0:0:1")).is_ok());
+        assert_eq!(parser.stats.missed, 0);
+        // 1 and 2 got lost
+        assert!(parser.parse(&v2("2:3:0:1")).is_ok());
+        assert_eq!(parser.stats.missed, 2);
+    }
+
+    #[test]
+    fn v1_passthrough() {
+        let mut parser = Parser::new();
+        assert_eq!(parser.parse("120:3:4:5"), Ok(reading(120, 3, vec![4, 5])));
+        assert_eq!(parser.parse("0:2"), Ok(reading(0, 2, vec![])));
+        match parser.parse("120:x") {
+            Err(FrameError::Malformed(_)) => {}
+            res => panic!("parsed as {:?}", res),
+        }
+    }
+
+    #[test]
+    fn not_text_counts_as_malformed() {
+        let mut parser = Parser::new();
+        parser.not_text(b"12\xff:3");
+        assert_eq!(parser.stats.malformed, 1);
+    }
+}
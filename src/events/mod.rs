@@ -0,0 +1,556 @@
+use std;
+use super::{EventTypes, Events};
+use serial;
+use std::io::BufRead;
+use serial::SerialPort;
+use std::net::UdpSocket;
+use rosc;
+use bit_set;
+use config;
+use console;
+use status;
+
+mod filter;
+mod frame;
+mod replay;
+mod sim;
+
+pub use self::filter::debounce;
+
+pub trait Eventer: std::marker::Send {
+    fn get_events(&mut self, sender: std::sync::mpsc::Sender<Events>);
+
+    fn get_timeout(&self) -> std::time::Duration;
+
+    /// where the events come from, for the recording
+    fn name(&self) -> &'static str;
+
+    /// whether its touches go through the debounce filter
+    fn debounced(&self) -> bool {
+        true
+    }
+}
+
+/// the console on stdin, see console.rs for the commands
+struct StdinEventSource {
+    config: config::ConfigHandle,
+    status: status::StatusHandle,
+}
+
+impl Eventer for StdinEventSource {
+    fn get_events(&mut self, sender: std::sync::mpsc::Sender<Events>) {
+        let mut console =
+            console::Console::new(self.config.clone(), self.status.clone(), sender, true);
+        let stdin = std::io::stdin();
+        // stdout is not locked for good, others print too
+        if let Err(e) = console.serve(stdin.lock(), &mut std::io::stdout()) {
+            error!("console stopped: {}", e);
+        }
+        info!("stdin closed, no more console commands");
+        loop {
+            std::thread::sleep(std::time::Duration::from_secs(1000));
+        }
+    }
+
+    fn get_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(1000)
+    }
+
+    fn name(&self) -> &'static str {
+        "stdin"
+    }
+}
+
+struct SerialEventSource {
+    devicefile: String,
+    config: config::ConfigHandle,
+    num_poles: usize,
+    /// poles the controller hasn't heard from in a while. kept when the port is reopened
+    offline: bit_set::BitSet,
+}
+
+impl SerialEventSource {
+    fn new(devicefile: &str, config: config::ConfigHandle) -> Self {
+        SerialEventSource {
+            devicefile: devicefile.to_string(),
+            num_poles: config.get_installation().num_poles,
+            config: config,
+            offline: bit_set::BitSet::new(),
+        }
+    }
+}
+
+impl Eventer for SerialEventSource {
+    fn get_events(&mut self, mut sender: std::sync::mpsc::Sender<Events>) {
+        loop {
+            let err = self.eventloop(&mut sender);
+            warn!("Event loop returned unxpectedly {:?}", err);
+            std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+    }
+
+    fn get_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(1000)
+    }
+
+    fn name(&self) -> &'static str {
+        "serial"
+    }
+}
+
+impl SerialEventSource {
+    #[cfg(target_os = "linux")]
+    fn auto_detect() -> String {
+        // /dev/ttyACM* or /dev/ttyUSB*
+        let dir = std::path::Path::new("/dev/");
+        let res = std::fs::read_dir(dir);
+        if let Ok(readir) = res {
+            for entry in readir {
+                if let Ok(entry) = entry {
+                    if let Ok(meta) = entry.metadata() {
+                        if !meta.is_dir() {
+                            if let Some(name) = entry.file_name().to_str() {
+                                if name.starts_with("ttyACM") || name.starts_with("ttyUSB") {
+                                    if let Some(path) = entry.path().to_str() {
+                                        return path.to_string();
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+
+        }
+
+        String::new()
+    }
+
+
+    #[cfg(not(target_os = "linux"))]
+    fn auto_detect() -> String {
+        panic!("Must provide serial device file name; for testing use filename \"stdin\" to get \
+                interactive input");
+    }
+
+    fn eventloop(&mut self, sender: &mut std::sync::mpsc::Sender<Events>) -> std::io::Result<()> {
+
+        let mut devicefile = self.devicefile.clone();
+        if devicefile.is_empty() {
+            devicefile = Self::auto_detect();
+        }
+
+        if devicefile.is_empty() {
+            use std::io::{Error, ErrorKind};
+            return Err(Error::new(ErrorKind::NotFound, "no serial device found"));
+        }
+
+        info!("Found serial device {}", devicefile);
+
+        let mut port = serial::open(&devicefile)?;
+        port.reconfigure(&|settings| {
+                settings.set_baud_rate(serial::Baud115200)?;
+                settings.set_char_size(serial::Bits8);
+                settings.set_parity(serial::ParityNone);
+                settings.set_stop_bits(serial::Stop1);
+                settings.set_flow_control(serial::FlowNone);
+                Ok(())
+            })?;
+
+        port.set_timeout(std::time::Duration::from_secs(10))?;
+
+        let mut reader = std::io::BufReader::new(port);
+
+        let mut buf = vec![];
+
+        let num_poles = self.num_poles;
+        let mut events = vec![vec![vec![false; num_poles]; num_poles]; 2];
+        let mut currentindex: usize = 0;
+        let mut pastindex: usize = 1;
+        let mut parser = frame::Parser::new();
+
+        sender.send(Events::Reset);
+
+        loop {
+            buf.clear();
+            reader.read_until(b'\n', &mut buf)?;
+            // line noise is dropped like any bad frame, not taken for a broken port
+            let line = match std::str::from_utf8(&buf) {
+                Ok(line) => line.trim(),
+                Err(_) => {
+                    let e = parser.not_text(&buf);
+                    warn!("dropped serial frame: {} ({} malformed so far)",
+                          e,
+                          parser.stats.malformed);
+                    continue;
+                }
+            };
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with("#") {
+                continue;
+            }
+
+            debug!("serial line: {}", line);
+            let missed = parser.stats.missed;
+            let reading = match parser.parse(line) {
+                Ok(reading) => reading,
+                Err(e @ frame::FrameError::BadCrc { .. }) => {
+                    warn!("dropped serial frame: {} ({} so far)", e, parser.stats.bad_crc);
+                    continue;
+                }
+                Err(e @ frame::FrameError::Malformed(_)) |
+                Err(e @ frame::FrameError::UnknownVersion(_)) => {
+                    warn!("dropped serial frame: {} ({} malformed so far)",
+                          e,
+                          parser.stats.malformed);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("error parsing serial line: {}", e);
+                    continue;
+                }
+            };
+            if parser.stats.missed > missed {
+                warn!("missed {} serial frames ({} so far)",
+                      parser.stats.missed - missed,
+                      parser.stats.missed);
+            }
+
+            let senderindex = reading.sender;
+            if senderindex >= num_poles {
+                warn!("serial line from unknown pole {}", senderindex);
+                continue;
+            }
+
+            let stale_after = self.config.get_sensors().serial_stale_after;
+            let stale_after = std::time::Duration::from_millis((stale_after * 1000.) as u64);
+            let lastheardof = reading.lastheardof;
+            let stale = lastheardof >= stale_after;
+            if stale && self.offline.insert(senderindex) {
+                warn!("pole {} is offline, last heard of {:?} ago", senderindex, lastheardof);
+                sender.send(Events::PoleOffline(senderindex));
+            } else if !stale && self.offline.remove(senderindex) {
+                info!("pole {} is back online", senderindex);
+                sender.send(Events::PoleOnline(senderindex));
+            }
+
+            // whatever a stale pole reports is old news
+            if !stale {
+                Self::set_events(&mut events[currentindex], senderindex, &reading.touching);
+            }
+
+            if senderindex == (num_poles - 1) {
+                // release the connections of offline poles, even if the other pole
+                // still reports them
+                for pole in self.offline.iter() {
+                    Self::clear_pole(&mut events[currentindex], pole);
+                }
+                Self::send_events(sender, &events[pastindex], &events[currentindex]);
+
+                std::mem::swap(&mut currentindex, &mut pastindex);
+
+                for e in events[currentindex].iter_mut() {
+                    for b in e.iter_mut() {
+                        *b = false;
+                    }
+                }
+            }
+        }
+    }
+
+    fn set_events(events: &mut [Vec<bool>], senderindex: usize, touches: &[usize]) {
+        let num_poles = events.len();
+        if senderindex >= num_poles {
+            return;
+        }
+        for ind in touches.iter().filter(|&x| *x < num_poles) {
+            events[senderindex][*ind] = true;
+            events[*ind][senderindex] = true;
+        }
+    }
+
+    fn clear_pole(events: &mut [Vec<bool>], pole: usize) {
+        for i in 0..events.len() {
+            events[pole][i] = false;
+            events[i][pole] = false;
+        }
+    }
+
+    fn send_events(sender: &mut std::sync::mpsc::Sender<Events>,
+                   pastevents: &[Vec<bool>],
+                   events: &[Vec<bool>]) {
+        let num_poles = events.len();
+        for i in 0..num_poles {
+            for j in i..num_poles {
+                if events[i][j] != pastevents[i][j] {
+                    let event = match events[i][j] {
+                        false => {
+                            debug!("Not Connected({},{})", i, j);
+                            Events::Stop(EventTypes::Connect(i, j))
+                        }
+                        true => {
+                            debug!("Connect({},{})", i, j);
+                            Events::Start(EventTypes::Connect(i, j))
+                        }
+                    };
+                    sender.send(event);
+                }
+            }
+        }
+
+    }
+}
+
+pub fn get_eventer(s: &str,
+                   config: config::ConfigHandle,
+                   status: status::StatusHandle)
+                   -> Option<Box<Eventer>> {
+    let num_poles = config.get_installation().num_poles;
+    match s  {
+        "stdin" =>
+        Some(Box::new(StdinEventSource {
+            config: config.with_source("stdin"),
+            status: status,
+        })),
+        "null"|"/dev/null"|"nil" => None,
+        "sim" => Some(Box::new(sim::SimEventSource::new("", num_poles))),
+        s if s.starts_with("sim:") =>
+        Some(Box::new(sim::SimEventSource::new(&s["sim:".len()..], num_poles))),
+        s if s.starts_with("replay:") =>
+        Some(Box::new(replay::ReplayEventSource::new(&s["replay:".len()..]))),
+    _ =>       Some(Box::new(SerialEventSource::new(s, config))),
+    }
+}
+
+
+
+pub struct UDPEventSource {
+    events: Vec<Vec<bool>>,
+    /// the last sequence number from each node, for nodes that send one
+    seqs: Vec<Option<i32>>,
+    /// when each node was last heard from. None if it never was, or went silent
+    last_heard: Vec<Option<std::time::Instant>>,
+    config: config::ConfigHandle,
+    socket: UdpSocket,
+}
+
+impl Eventer for UDPEventSource {
+    fn get_events(&mut self, mut sender: std::sync::mpsc::Sender<Events>) {
+        
+        info!("osc event server up");
+        // wake up now and then to notice nodes that went silent
+        if let Err(e) = self.socket.set_read_timeout(Some(std::time::Duration::from_secs(1))) {
+            warn!("can't set the udp read timeout, silent nodes won't be released: {}", e);
+        }
+        let mut buf = [0; 4096];
+        loop {
+            let res = self.socket.recv_from(&mut buf);
+            self.release_silent_nodes(&sender);
+            if res.is_err() {
+                // TODO log
+                continue;
+            }
+            let (amt, src) = res.unwrap();  
+            let buf = &mut buf[..amt];
+
+            debug!("Received udp packet {}",amt);
+            
+
+            let res = rosc::decoder::decode(&buf);
+            let msg = match res {
+                Err(e) => {
+
+                warn!("Error parsing packet udp event {:?}", e);
+                continue;
+                }
+                Ok(msg) => msg,
+            };
+
+            debug!("got event osc packet {:?}", &msg);
+            self.process(&sender, msg);
+        }
+    }
+
+
+    fn get_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(1000)
+    }
+
+    fn name(&self) -> &'static str {
+        "udp"
+    }
+}
+
+
+/// a node still heard from must have sent more packets than this before a 0
+/// from it is taken for a restart
+const MIN_SEQ_BEFORE_RESTART: i32 = 100;
+
+impl UDPEventSource {
+
+    pub fn new(addr: &str, config: config::ConfigHandle) -> std::io::Result<Self> {
+        let num_poles = config.get_installation().num_poles;
+        Ok(UDPEventSource {
+            events: vec![vec![false; num_poles]; num_poles],
+            seqs: vec![None; num_poles],
+            last_heard: vec![None; num_poles],
+            config: config,
+            socket : UdpSocket::bind(addr)?,
+        })
+    }
+
+    /// release the touches of nodes that weren't heard from within the timeout
+    fn release_silent_nodes(&mut self, sender: &std::sync::mpsc::Sender<Events>) {
+        let timeout = self.config.get_sensors().udp_timeout;
+        let timeout = std::time::Duration::from_millis((timeout * 1000.) as u64);
+        let now = std::time::Instant::now();
+        for id in 0..self.last_heard.len() {
+            match self.last_heard[id] {
+                Some(last) if now - last > timeout => {}
+                _ => continue,
+            }
+            warn!("pole node {} went silent, releasing its touches", id);
+            self.last_heard[id] = None;
+            let released = vec![false; self.events.len()];
+            self.update_node(sender, id, released);
+            sender.send(Events::PoleOffline(id));
+        }
+    }
+
+    /// false for a packet that is older than, or the same as, the last one from this node
+    fn is_newer(&mut self, id: usize, seq: i32) -> bool {
+        if let Some(last) = self.seqs[id] {
+            // a node that restarted counts from 0 again. a late 0 from a node
+            // that just started and is still heard is not a restart
+            let restarted = seq == 0 &&
+                            (self.last_heard[id].is_none() || last > MIN_SEQ_BEFORE_RESTART);
+            if !restarted && seq.wrapping_sub(last) <= 0 {
+                return false;
+            }
+        }
+        self.seqs[id] = Some(seq);
+        true
+    }
+
+    fn process(&mut self, sender: &std::sync::mpsc::Sender<Events>,
+               p: rosc::OscPacket) {
+       match p {
+            rosc::OscPacket::Message(m) => {
+                self.process_message( sender, m);
+            }
+            rosc::OscPacket::Bundle(b) => {
+                // we ignore time tag. sorry.
+                for inner in b.content {
+                    self.process( sender, inner);
+                }
+            }
+        }
+    }
+
+
+// /pole_touch <blob> [<seq>]
+// blob is the node id, one bit per pole and a checksum, see parse_touch_packet.
+// seq is optional, and counts up with each packet a node sends
+
+
+    fn process_message(&mut self, sender: &std::sync::mpsc::Sender<Events>,
+                       m: rosc::OscMessage) {
+        match (m.addr.as_ref(), &m.args) {
+            ("/pole_touch", &Some(ref args)) if (args.len() == 1 || args.len() == 2) => {
+                let packet = match &args[0] {
+                    &rosc::OscType::Blob(ref packet) => packet,
+                    _ => {warn!("unexpected pole_touch packet {:?}", args[0]); return;}
+                };
+                let seq = match args.get(1) {
+                    None => None,
+                    Some(&rosc::OscType::Int(seq)) => Some(seq),
+                    Some(arg) => {warn!("unexpected pole_touch sequence {:?}", arg); return;}
+                };
+
+                let num_poles = self.events.len();
+                let (id, currentstate) = match parse_touch_packet(packet, num_poles) {
+                    Ok(touch) => touch,
+                    Err(e) => {
+                        warn!("dropped pole_touch packet {:?}: {}", packet, e);
+                        return;
+                    }
+                };
+
+                if let Some(seq) = seq {
+                    if !self.is_newer(id, seq) {
+                        debug!("dropped old pole_touch {} from {}", seq, id);
+                        return;
+                    }
+                }
+
+                if self.last_heard[id].is_none() {
+                    sender.send(Events::PoleOnline(id));
+                }
+                self.last_heard[id] = Some(std::time::Instant::now());
+                self.update_node(sender, id, currentstate);
+            }
+            _ => {warn!("got event unexpected msg {:?}", m);}
+        }
+    }
+
+    /// send the connections of node id that changed, and remember its new state
+    fn update_node(&mut self,
+                   sender: &std::sync::mpsc::Sender<Events>,
+                   id: usize,
+                   currentstate: Vec<bool>) {
+        for j in 0..currentstate.len() {
+            let past_state = self.events[id][j];
+            let transpose_past_state =  
+            if j == id {
+                false
+            } else {
+                self.events[j][id]
+            };
+
+            let event = match (currentstate[j], past_state, transpose_past_state) {
+                (false, true, false)  => {
+                        debug!("udp Not Connected({},{})", id, j);
+                        Events::Stop(EventTypes::Connect(id, j))
+                }
+                (true, false, false) => {
+                        debug!("udp Connect({},{})", id, j);
+                        Events::Start(EventTypes::Connect(id, j))
+                }
+                _ => {
+                    // nothing to do...
+                    continue;
+                }
+            };
+            sender.send(event);
+        }
+        self.events[id] = currentstate;
+    }
+
+}
+
+/// a /pole_touch blob: the node id, one bit per pole (lowest bit first), and
+/// the sum of the previous bytes as checksum. returns the id and the poles it touches.
+fn parse_touch_packet(packet: &[u8], num_poles: usize) -> Result<(usize, Vec<bool>), String> {
+    let bitmap_len = (num_poles + 7) / 8;
+    if packet.len() != bitmap_len + 2 {
+        return Err(format!("{} bytes, expected {}", packet.len(), bitmap_len + 2));
+    }
+
+    let (data, checksum) = (&packet[..packet.len() - 1], packet[packet.len() - 1]);
+    let sum = data.iter().fold(0u8, |acc, &x| acc.wrapping_add(x));
+    if sum != checksum {
+        return Err(format!("checksum {} != {}", sum, checksum));
+    }
+
+    let id = data[0] as usize;
+    if id >= num_poles {
+        return Err(format!("invalid id {}", id));
+    }
+
+    let bitmap = &data[1..];
+    let touching = (0..num_poles).map(|i| ((bitmap[i >> 3] >> (i & 0b111)) & 0b1) != 0).collect();
+    Ok((id, touching))
+}
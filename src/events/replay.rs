@@ -0,0 +1,115 @@
+use std;
+use std::io::BufRead;
+use serde_json;
+
+use super::super::Events;
+use super::Eventer;
+use record;
+
+/// plays back a recording made with --record, with its original timing.
+/// device string: replay:<file>[,speed=<multiplier>][,loop]
+pub struct ReplayEventSource {
+    path: String,
+    speed: f64,
+    looping: bool,
+}
+
+impl ReplayEventSource {
+    pub fn new(spec: &str) -> Self {
+        let mut parts = spec.split(',');
+        let mut replay = ReplayEventSource {
+            path: parts.next().unwrap_or("").to_string(),
+            speed: 1.,
+            looping: false,
+        };
+        for option in parts {
+            if option == "loop" {
+                replay.looping = true;
+            } else if option.starts_with("speed=") {
+                match option["speed=".len()..].parse::<f64>() {
+                    Ok(speed) if speed > 0. => replay.speed = speed,
+                    _ => error!("ignoring invalid replay {}", option),
+                }
+            } else {
+                error!("ignoring unknown replay option {}", option);
+            }
+        }
+        replay
+    }
+
+    fn play(&self, sender: &std::sync::mpsc::Sender<Events>) -> std::io::Result<()> {
+        let file = std::io::BufReader::new(std::fs::File::open(&self.path)?);
+        info!("replaying {} at {}x", self.path, self.speed);
+
+        let start = std::time::Instant::now();
+        let mut first_t = None;
+        for line in file.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: record::Entry = match serde_json::from_str(&line) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("skipping replay line {:?}: {}", line, e);
+                    continue;
+                }
+            };
+
+            // the recording's clock starts at startup, ours at the first event
+            if first_t.is_none() {
+                first_t = Some(entry.t);
+            }
+            let at = ((entry.t - first_t.unwrap()) / self.speed).max(0.);
+            let at = std::time::Duration::new(at as u64, (at.fract() * 1_000_000_000.) as u32);
+            let elapsed = start.elapsed();
+            if at > elapsed {
+                std::thread::sleep(at - elapsed);
+            }
+
+            match entry.event {
+                // the config file is ours, not the recording's
+                Events::SaveConfig | Events::Draw => {}
+                event => {
+                    debug!("replaying {:?} from {}", event, entry.source);
+                    sender.send(event);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Eventer for ReplayEventSource {
+    fn get_events(&mut self, sender: std::sync::mpsc::Sender<Events>) {
+        loop {
+            if let Err(e) = self.play(&sender) {
+                error!("can't replay {}: {}", self.path, e);
+            }
+            // let go of whatever the recording left touched
+            sender.send(Events::Reset);
+            if !self.looping {
+                info!("replay of {} done", self.path);
+                loop {
+                    std::thread::sleep(std::time::Duration::from_secs(1000));
+                }
+            }
+            // don't spin on a missing or empty file
+            std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+    }
+
+    fn get_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(1000)
+    }
+
+    fn name(&self) -> &'static str {
+        "replay"
+    }
+
+    /// the touches were recorded after the debounce filter, so they are
+    /// replayed without it, at the time they were passed on
+    fn debounced(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,171 @@
+use std;
+use rand::{Rng, SeedableRng, StdRng};
+use bit_set::BitSet;
+
+use super::super::{EventTypes, Events};
+use super::Eventer;
+
+/// virtual visitors that touch poles, alone or holding hands in a chain.
+/// device string: sim[:rate=<per minute>,chain=<0..1>,max_chain=<poles>,hold=<secs>,seed=<n>]
+pub struct SimEventSource {
+    num_poles: usize,
+    /// visitors arriving per minute, on average
+    rate: f64,
+    /// how likely a visitor brings friends and forms a chain
+    chain: f64,
+    /// the most poles a chain connects
+    max_chain: usize,
+    /// how long a visitor stays, on average
+    hold: f64,
+    seed: usize,
+}
+
+/// someone touching poles until they leave
+struct Visitor {
+    poles: Vec<usize>,
+    leaves: std::time::Instant,
+}
+
+impl SimEventSource {
+    pub fn new(spec: &str, num_poles: usize) -> Self {
+        let mut sim = SimEventSource {
+            num_poles: num_poles,
+            rate: 6.,
+            chain: 0.3,
+            max_chain: 4,
+            hold: 8.,
+            seed: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs() as usize)
+                .unwrap_or(0),
+        };
+        for option in spec.split(',').filter(|o| !o.is_empty()) {
+            let mut kv = option.splitn(2, '=');
+            let (key, value) = (kv.next().unwrap_or(""), kv.next().unwrap_or(""));
+            let ok = match key {
+                "rate" => parse_into(value, &mut sim.rate, |v| v > 0.),
+                "chain" => parse_into(value, &mut sim.chain, |v| v >= 0. && v <= 1.),
+                "max_chain" => parse_into(value, &mut sim.max_chain, |v| v >= 2),
+                "hold" => parse_into(value, &mut sim.hold, |v| v > 0.),
+                "seed" => parse_into(value, &mut sim.seed, |_| true),
+                _ => false,
+            };
+            if !ok {
+                error!("ignoring invalid sim option {}", option);
+            }
+        }
+        sim
+    }
+
+    /// the poles of a new visitor: one, or a chain of 2 to max_chain. only poles
+    /// nobody touches, so one visitor leaving doesn't release another.
+    fn pick_poles<R: Rng>(&self, rng: &mut R, busy: &BitSet) -> Vec<usize> {
+        let mut free: Vec<usize> = (0..self.num_poles).filter(|p| !busy.contains(*p)).collect();
+        rng.shuffle(&mut free);
+        let count = if rng.gen::<f64>() < self.chain {
+            rng.gen_range(2, self.max_chain + 1)
+        } else {
+            1
+        };
+        free.truncate(count);
+        free
+    }
+
+    fn send(sender: &std::sync::mpsc::Sender<Events>, poles: &[usize], start: bool) {
+        // a single pole touches itself, a chain connects each pole to the next
+        let pairs: Vec<(usize, usize)> = if poles.len() == 1 {
+            vec![(poles[0], poles[0])]
+        } else {
+            poles.windows(2).map(|w| (w[0], w[1])).collect()
+        };
+        for (p1, p2) in pairs {
+            let event = if start {
+                Events::Start(EventTypes::Connect(p1, p2))
+            } else {
+                Events::Stop(EventTypes::Connect(p1, p2))
+            };
+            sender.send(event);
+        }
+    }
+}
+
+impl Eventer for SimEventSource {
+    fn get_events(&mut self, sender: std::sync::mpsc::Sender<Events>) {
+        info!("simulating visitors: {} per minute, chain {}, up to {} poles, {}s, seed {}",
+              self.rate,
+              self.chain,
+              self.max_chain,
+              self.hold,
+              self.seed);
+        let mut rng: StdRng = SeedableRng::from_seed(&[self.seed][..]);
+        let mut visitors: Vec<Visitor> = vec![];
+        let mut busy = BitSet::new();
+        let mut next_arrival = std::time::Instant::now();
+
+        sender.send(Events::Reset);
+        loop {
+            let now = std::time::Instant::now();
+
+            for v in visitors.iter().filter(|v| v.leaves <= now) {
+                debug!("sim visitor leaves {:?}", v.poles);
+                Self::send(&sender, &v.poles, false);
+                for p in v.poles.iter() {
+                    busy.remove(*p);
+                }
+            }
+            visitors.retain(|v| v.leaves > now);
+
+            if next_arrival <= now {
+                let poles = self.pick_poles(&mut rng, &busy);
+                // a visitor that finds all poles taken walks on
+                if !poles.is_empty() {
+                    debug!("sim visitor arrives at {:?}", poles);
+                    Self::send(&sender, &poles, true);
+                    for p in poles.iter() {
+                        busy.insert(*p);
+                    }
+                    let stay = exponential(&mut rng, self.hold);
+                    visitors.push(Visitor {
+                        poles: poles,
+                        leaves: now + stay,
+                    });
+                }
+                next_arrival = now + exponential(&mut rng, 60. / self.rate);
+            }
+
+            let wake = visitors.iter().map(|v| v.leaves).fold(next_arrival, std::cmp::min);
+            let now = std::time::Instant::now();
+            if wake > now {
+                std::thread::sleep(wake - now);
+            }
+        }
+    }
+
+    fn get_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(1000)
+    }
+
+    fn name(&self) -> &'static str {
+        "sim"
+    }
+}
+
+fn parse_into<T, F>(value: &str, into: &mut T, valid: F) -> bool
+    where T: std::str::FromStr + Copy,
+          F: Fn(T) -> bool
+{
+    match value.parse::<T>() {
+        Ok(v) if valid(v) => {
+            *into = v;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// a random wait with the given mean, as between independent arrivals
+fn exponential<R: Rng>(rng: &mut R, mean: f64) -> std::time::Duration {
+    // gen is in [0, 1); keep away from ln(0)
+    let secs = -mean * (1. - rng.gen::<f64>()).ln();
+    std::time::Duration::new(secs as u64, (secs.fract() * 1_000_000_000.) as u32)
+}
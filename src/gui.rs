@@ -58,9 +58,9 @@ pub struct UI{
 }
 
 impl UI{
-    fn new(rx: std::sync::mpsc::Receiver<Vec<Pixel>>) -> Self {
+    fn new(rx: std::sync::mpsc::Receiver<Vec<Pixel>>, layout: &str) -> Self {
 
-    let file = std::fs::File::open(std::env::var("LAYOUT").unwrap()).unwrap();
+    let file = std::fs::File::open(layout).expect("can't open the layout file");
     let pts: Vec<Point> = serde_json::from_reader(file).unwrap();
 
     let mut window = Window::new("Kiss3d: cube");
@@ -98,8 +98,8 @@ pub fn start_ui(&mut self) {
 
 }
 
-pub fn create_gui() -> (Option<Box<pixels::LedArray+Send>> , Option<UI>){
+pub fn create_gui(size: usize, layout: &str) -> (Option<Box<pixels::LedArray+Send>> , Option<UI>){
     let (tx, rx) = std::sync::mpsc::channel();
-    (Some(Box::new(GuiLedArray::new(super::NUM_POLES * super::LEDS_PER_STRING, tx))), Some(UI::new(rx)))
+    (Some(Box::new(GuiLedArray::new(size, tx))), Some(UI::new(rx, layout)))
 }
 
@@ -0,0 +1,413 @@
+use std;
+use std::io::{BufRead, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use serde;
+use serde_json;
+
+use super::{EventTypes, Events, Modes, PoleState};
+use config;
+use status;
+
+mod stream;
+mod websocket;
+
+pub use self::stream::{Frame, Frames};
+
+/// request bodies bigger than this are refused
+const MAX_BODY: usize = 64 * 1024;
+/// and so are request and header lines longer than this
+const MAX_LINE: usize = 8 * 1024;
+/// or more headers than this
+const MAX_HEADERS: usize = 64;
+/// each client has a thread, websocket streams for as long as they watch.
+/// clients past this many are answered 503 right away
+const MAX_CLIENTS: usize = 16;
+
+/// a json api for dashboards: poles, status, config, mode, touches and presets,
+/// and a websocket stream of the frames. touches and modes are sent as events to
+/// the work loop, config changes go through the config handle like those from osc.
+pub fn start(addr: &str,
+             config: config::ConfigHandle,
+             status: status::StatusHandle,
+             frames: Frames,
+             sender: std::sync::mpsc::Sender<Events>)
+             -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!("http api listening on {}", addr);
+    let api = Api {
+        config: config.with_source("http"),
+        status: status,
+        frames: frames,
+        sender: sender,
+    };
+    let clients = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(mut stream) => {
+                    // only this thread adds clients, so the count can't go past the limit
+                    if clients.load(std::sync::atomic::Ordering::SeqCst) >= MAX_CLIENTS {
+                        warn!("refused an http client, {} are connected already", MAX_CLIENTS);
+                        let _ = stream.set_write_timeout(Some(std::time::Duration::from_secs(1)));
+                        let _ = Response::error(503, "Service Unavailable", "too many clients")
+                            .write_to(&mut stream);
+                        continue;
+                    }
+                    let client = Client::new(clients.clone());
+                    let api = api.clone();
+                    std::thread::spawn(move || {
+                        api.serve(stream);
+                        drop(client);
+                    });
+                }
+                Err(e) => warn!("can't accept an http client: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// counts a connected client until it is dropped
+struct Client {
+    count: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl Client {
+    fn new(count: std::sync::Arc<std::sync::atomic::AtomicUsize>) -> Self {
+        count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Client { count: count }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    /// names are lowercase
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn read<R: BufRead>(input: &mut R) -> Result<Request, String> {
+        let line = read_line(input)?;
+        let mut words = line.split_whitespace();
+        let (method, target) = match (words.next(), words.next()) {
+            (Some(method), Some(target)) => (method.to_string(), target),
+            _ => return Err(format!("bad request line {:?}", line)),
+        };
+        let mut target = target.splitn(2, '?');
+        let path = target.next().unwrap_or("").trim_right_matches('/').to_string();
+        let query = target.next().unwrap_or("").to_string();
+
+        let mut headers = vec![];
+        loop {
+            let line = read_line(input)?;
+            let line = line.trim_right();
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Err(format!("more than {} headers", MAX_HEADERS));
+            }
+            let mut kv = line.splitn(2, ':');
+            if let (Some(name), Some(value)) = (kv.next(), kv.next()) {
+                headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+            }
+        }
+
+        let mut request = Request {
+            method: method,
+            path: path,
+            query: query,
+            headers: headers,
+            body: vec![],
+        };
+        let length = match request.header("content-length") {
+            Some(length) => length.parse::<usize>().map_err(|_| "bad content-length".to_string())?,
+            None => 0,
+        };
+        if length > MAX_BODY {
+            return Err(format!("the body is bigger than {} bytes", MAX_BODY));
+        }
+        request.body = vec![0; length];
+        input.read_exact(&mut request.body).map_err(|e| e.to_string())?;
+        Ok(request)
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|h| h.0 == name).map(|h| &h.1[..])
+    }
+
+    /// whether the query has name, as name or name=1
+    fn flag(&self, name: &str) -> bool {
+        self.query.split('&').any(|q| q == name || q == format!("{}=1", name))
+    }
+
+    fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T, Response> {
+        serde_json::from_slice(&self.body)
+            .map_err(|e| Response::error(400, "Bad Request", &format!("invalid body: {}", e)))
+    }
+}
+
+/// a line, read no further than MAX_LINE
+fn read_line<R: BufRead>(input: &mut R) -> Result<String, String> {
+    let mut line = String::new();
+    input.by_ref().take(MAX_LINE as u64 + 1).read_line(&mut line).map_err(|e| e.to_string())?;
+    if line.len() > MAX_LINE {
+        return Err(format!("a line is longer than {} bytes", MAX_LINE));
+    }
+    Ok(line)
+}
+
+struct Response {
+    status: u16,
+    reason: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json<T: serde::Serialize>(value: &T) -> Response {
+        Response {
+            status: 200,
+            reason: "OK",
+            body: serde_json::to_string(value).expect("api replies are plain data"),
+        }
+    }
+
+    fn ok() -> Response {
+        Response::json(&serde_json::Value::Object(serde_json::Map::new()))
+    }
+
+    fn error(status: u16, reason: &'static str, message: &str) -> Response {
+        let mut body = serde_json::Map::new();
+        body.insert("error".to_string(), serde_json::Value::String(message.to_string()));
+        Response {
+            status: status,
+            reason: reason,
+            body: serde_json::Value::Object(body).to_string(),
+        }
+    }
+
+    fn not_found() -> Response {
+        Response::error(404, "Not Found", "no such endpoint")
+    }
+
+    fn write_to<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        write!(out,
+               "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+                Access-Control-Allow-Origin: *\r\n\
+                Access-Control-Allow-Methods: GET, POST, PUT, PATCH, DELETE\r\n\
+                Access-Control-Allow-Headers: Content-Type\r\n\
+                Connection: close\r\n\r\n{}",
+               self.status,
+               self.reason,
+               self.body.len(),
+               self.body)?;
+        out.flush()
+    }
+}
+
+#[derive(Serialize)]
+struct PoleReply {
+    pole: usize,
+    /// not_touched, touched or connected
+    state: &'static str,
+    connected_to: Vec<usize>,
+    /// touching, connecting, exploding or null
+    anim: Option<&'static str>,
+    level: f32,
+    touch_level: f32,
+    offline: bool,
+}
+
+#[derive(Serialize)]
+struct StatusReply {
+    mode: Modes,
+    brightness: f32,
+    offline: Vec<usize>,
+}
+
+#[derive(Deserialize)]
+struct ModeRequest {
+    mode: Modes,
+}
+
+#[derive(Deserialize)]
+struct TouchRequest {
+    /// one pole touches itself, more connect each pole to the next
+    poles: Vec<usize>,
+    /// let go after this many seconds
+    #[serde(rename = "for")]
+    release_after: Option<f32>,
+}
+
+#[derive(Clone)]
+struct Api {
+    config: config::ConfigHandle,
+    status: status::StatusHandle,
+    frames: Frames,
+    sender: std::sync::mpsc::Sender<Events>,
+}
+
+impl Api {
+    fn serve(&self, stream: TcpStream) {
+        // don't keep a thread for a client that never finishes its request
+        let _ = stream.set_read_timeout(Some(std::time::Duration::from_secs(10)));
+        let request = Request::read(&mut std::io::BufReader::new(&stream));
+        let response = match request {
+            Ok(request) => {
+                debug!("http {} {}", request.method, request.path);
+                if request.method == "GET" && request.path == "/stream" {
+                    match request.header("sec-websocket-key") {
+                        Some(key) => {
+                            let _ = stream.set_read_timeout(None);
+                            stream::serve(stream, key, self.frames.clone(), request.flag("pixels"));
+                            return;
+                        }
+                        None => Response::error(400, "Bad Request", "not a websocket upgrade"),
+                    }
+                } else {
+                    match self.route(&request) {
+                        Ok(response) | Err(response) => response,
+                    }
+                }
+            }
+            Err(e) => Response::error(400, "Bad Request", &e),
+        };
+        if let Err(e) = response.write_to(&mut &stream) {
+            debug!("can't send the http reply: {}", e);
+        }
+    }
+
+    fn route(&self, request: &Request) -> Result<Response, Response> {
+        let path = &request.path[..];
+        match (&request.method[..], path) {
+            // cors preflight from browser dashboards
+            ("OPTIONS", _) => Ok(Response::ok()),
+            ("GET", "/poles") => Ok(Response::json(&self.poles())),
+            ("GET", _) if path.starts_with("/poles/") => self.pole(&path["/poles/".len()..]),
+            ("GET", "/status") => Ok(Response::json(&self.status())),
+            ("GET", "/config") => Ok(Response::json(&self.config.to_json())),
+            ("PATCH", "/config") => {
+                let patch: serde_json::Value = request.json()?;
+                self.config.patch(&patch).map(|_| Response::ok()).map_err(bad_request)
+            }
+            ("PUT", "/mode") => {
+                let body: ModeRequest = request.json()?;
+                self.sender.send(Events::ModeChanged(body.mode));
+                Ok(Response::ok())
+            }
+            ("POST", "/touches") => {
+                let body: TouchRequest = request.json()?;
+                self.touch(&body, true)
+            }
+            ("DELETE", "/touches") => {
+                let body: TouchRequest = request.json()?;
+                self.touch(&body, false)
+            }
+            ("POST", "/reset") => {
+                self.sender.send(Events::Reset);
+                Ok(Response::ok())
+            }
+            ("GET", "/presets") => Ok(Response::json(&self.config.get_preset_names())),
+            ("POST", _) if path.starts_with("/presets/") => {
+                self.config
+                    .load_preset(&path["/presets/".len()..])
+                    .map(|_| Response::ok())
+                    .map_err(bad_request)
+            }
+            _ => Err(Response::not_found()),
+        }
+    }
+
+    fn poles(&self) -> Vec<PoleReply> {
+        pole_replies(&self.status.get())
+    }
+
+    fn pole(&self, index: &str) -> Result<Response, Response> {
+        let poles = self.poles();
+        match index.parse::<usize>().ok().and_then(|i| poles.get(i)) {
+            Some(pole) => Ok(Response::json(pole)),
+            None => Err(Response::error(404, "Not Found", &format!("there is no pole {}", index))),
+        }
+    }
+
+    fn status(&self) -> StatusReply {
+        let status = self.status.get();
+        StatusReply {
+            mode: status.mode,
+            brightness: status.brightness,
+            offline: status.offline.iter().collect(),
+        }
+    }
+
+    fn touch(&self, body: &TouchRequest, start: bool) -> Result<Response, Response> {
+        let num_poles = self.config.get_installation().num_poles;
+        if body.poles.is_empty() {
+            return Err(Response::error(400, "Bad Request", "no poles"));
+        }
+        if let Some(pole) = body.poles.iter().find(|p| **p >= num_poles) {
+            return Err(Response::error(400, "Bad Request", &format!("there is no pole {}", pole)));
+        }
+
+        let pairs: Vec<(usize, usize)> = if body.poles.len() == 1 {
+            vec![(body.poles[0], body.poles[0])]
+        } else {
+            body.poles.windows(2).map(|w| (w[0], w[1])).collect()
+        };
+        for &(p1, p2) in pairs.iter() {
+            let event = if start {
+                Events::Start(EventTypes::Connect(p1, p2))
+            } else {
+                Events::Stop(EventTypes::Connect(p1, p2))
+            };
+            self.sender.send(event);
+        }
+
+        match body.release_after {
+            Some(secs) if start && secs >= 0. => {
+                let sender = self.sender.clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(std::time::Duration::from_millis((secs * 1000.) as u64));
+                    for (p1, p2) in pairs {
+                        sender.send(Events::Stop(EventTypes::Connect(p1, p2)));
+                    }
+                });
+            }
+            _ => {}
+        }
+        Ok(Response::ok())
+    }
+}
+
+fn pole_replies(status: &status::Status) -> Vec<PoleReply> {
+    status.poles
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            PoleReply {
+                pole: i,
+                state: p.state.name(),
+                connected_to: match p.state {
+                    PoleState::ConnectedTo(ref others) => others.iter().collect(),
+                    _ => vec![],
+                },
+                anim: p.anim.map(|a| a.name()),
+                level: p.level,
+                touch_level: p.touch_level,
+                offline: status.offline.contains(i),
+            }
+        })
+        .collect()
+}
+
+fn bad_request(e: config::ConfigError) -> Response {
+    Response::error(400, "Bad Request", &e.to_string())
+}
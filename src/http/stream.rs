@@ -0,0 +1,201 @@
+use std;
+use std::net::TcpStream;
+use std::sync::{Arc, Condvar, Mutex};
+use serde_json;
+
+use super::super::Modes;
+use super::{websocket, PoleReply};
+use status;
+
+/// the latest drawn frame, for the websocket clients. the work loop replaces it
+/// on every draw and never waits for a client; a slow client skips frames.
+#[derive(Clone)]
+pub struct Frames {
+    shared: Arc<(Mutex<Slot>, Condvar)>,
+}
+
+/// what the leds show after a draw
+pub struct Frame {
+    pub status: status::Status,
+    /// rgb for each led of each pole in turn, when a client asked for them
+    pub pixels: Option<Vec<u8>>,
+}
+
+struct Slot {
+    frame: Option<Arc<Frame>>,
+    seq: u64,
+    clients: usize,
+    pixel_clients: usize,
+}
+
+impl Frames {
+    pub fn new() -> Self {
+        let slot = Slot {
+            frame: None,
+            seq: 0,
+            clients: 0,
+            pixel_clients: 0,
+        };
+        Frames { shared: Arc::new((Mutex::new(slot), Condvar::new())) }
+    }
+
+    /// None if nobody watches, otherwise whether someone wants the pixels.
+    /// saves the work loop making frames for nobody.
+    pub fn wanted(&self) -> Option<bool> {
+        let slot = self.shared.0.lock().unwrap();
+        if slot.clients == 0 {
+            None
+        } else {
+            Some(slot.pixel_clients > 0)
+        }
+    }
+
+    pub fn publish(&self, frame: Frame) {
+        let &(ref slot, ref fresh) = &*self.shared;
+        {
+            let mut slot = slot.lock().unwrap();
+            slot.frame = Some(Arc::new(frame));
+            slot.seq += 1;
+        }
+        fresh.notify_all();
+    }
+
+    fn subscribe(&self, pixels: bool) -> Subscription {
+        let mut slot = self.shared.0.lock().unwrap();
+        slot.clients += 1;
+        if pixels {
+            slot.pixel_clients += 1;
+        }
+        Subscription {
+            frames: self.clone(),
+            pixels: pixels,
+            seen: slot.seq,
+        }
+    }
+}
+
+/// a client's place in the frames. leaving unsubscribes.
+struct Subscription {
+    frames: Frames,
+    pixels: bool,
+    seen: u64,
+}
+
+impl Subscription {
+    /// wait for a frame newer than the last one, and take the latest
+    fn next(&mut self) -> (u64, Arc<Frame>) {
+        let &(ref slot, ref fresh) = &*self.frames.shared;
+        let mut slot = slot.lock().unwrap();
+        loop {
+            if slot.seq > self.seen {
+                if let Some(ref frame) = slot.frame {
+                    self.seen = slot.seq;
+                    return (slot.seq, frame.clone());
+                }
+            }
+            slot = fresh.wait(slot).unwrap();
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut slot = self.frames.shared.0.lock().unwrap();
+        slot.clients -= 1;
+        if self.pixels {
+            slot.pixel_clients -= 1;
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct FrameMessage {
+    seq: u64,
+    mode: Modes,
+    brightness: f32,
+    poles: Vec<PoleReply>,
+    /// base64 of the rgb bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pixels: Option<String>,
+}
+
+/// control frames are at most this long, and the client has nothing else to say
+const MAX_CLIENT_FRAME: usize = 125;
+
+/// send the frames to an upgraded connection until it goes away or closes
+pub fn serve(stream: TcpStream, key: &str, frames: Frames, pixels: bool) {
+    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_else(|_| "unknown".to_string());
+    // a client that stops reading is dropped rather than kept forever
+    let _ = stream.set_write_timeout(Some(std::time::Duration::from_secs(10)));
+    if let Err(e) = websocket::handshake(&mut &stream, key) {
+        debug!("websocket handshake with {} failed: {}", peer, e);
+        return;
+    }
+    info!("websocket client {} connected", peer);
+
+    let input = match stream.try_clone() {
+        Ok(input) => input,
+        Err(e) => {
+            warn!("can't read from websocket client {}: {}", peer, e);
+            return;
+        }
+    };
+    // both threads write, a frame at a time
+    let output = Arc::new(Mutex::new(stream));
+    {
+        let output = output.clone();
+        let peer = peer.clone();
+        std::thread::spawn(move || read_client(input, output, &peer));
+    }
+
+    let mut subscription = frames.subscribe(pixels);
+    loop {
+        let (seq, frame) = subscription.next();
+        // a frame made before a pixel client came has no pixels yet
+        let message = FrameMessage {
+            seq: seq,
+            mode: frame.status.mode,
+            brightness: frame.status.brightness,
+            poles: super::pole_replies(&frame.status),
+            pixels: match frame.pixels {
+                Some(ref rgb) if pixels => Some(websocket::base64(rgb)),
+                _ => None,
+            },
+        };
+        let text = serde_json::to_string(&message).expect("frames are plain data");
+        if let Err(e) = websocket::send_text(&mut *output.lock().unwrap(), &text) {
+            info!("websocket client {} left: {}", peer, e);
+            // and the reading thread with it
+            let _ = output.lock().unwrap().shutdown(std::net::Shutdown::Both);
+            return;
+        }
+    }
+}
+
+/// answer the client's pings and its close. once the connection is done it's
+/// shut, so the next frame sent fails and the sending thread ends too.
+fn read_client(mut input: TcpStream, output: Arc<Mutex<TcpStream>>, peer: &str) {
+    loop {
+        match websocket::read(&mut input, MAX_CLIENT_FRAME) {
+            Ok((websocket::CLOSE, payload)) => {
+                debug!("websocket client {} closes", peer);
+                // echo the status code, if it sent one
+                let code: &[u8] = if payload.len() >= 2 { &payload[..2] } else { &[] };
+                let _ = websocket::send(&mut *output.lock().unwrap(), websocket::CLOSE, code);
+                break;
+            }
+            Ok((websocket::PING, payload)) => {
+                if websocket::send(&mut *output.lock().unwrap(), websocket::PONG, &payload)
+                    .is_err() {
+                    break;
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                debug!("websocket client {}: {}", peer, e);
+                break;
+            }
+        }
+    }
+    let _ = input.shutdown(std::net::Shutdown::Both);
+}
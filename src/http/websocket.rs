@@ -0,0 +1,208 @@
+use std;
+use std::io::{Read, Write};
+
+/// appended to the client's key before hashing, from rfc 6455
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+pub const TEXT: u8 = 0x1;
+pub const CLOSE: u8 = 0x8;
+pub const PING: u8 = 0x9;
+pub const PONG: u8 = 0xa;
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// the reply to a websocket upgrade request with the given Sec-WebSocket-Key
+pub fn handshake<W: Write>(out: &mut W, key: &str) -> std::io::Result<()> {
+    let mut keyed = key.trim().to_string();
+    keyed.push_str(ACCEPT_GUID);
+    let accept = base64(&sha1(keyed.as_bytes()));
+    write!(out,
+           "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+            Sec-WebSocket-Accept: {}\r\n\r\n",
+           accept)?;
+    out.flush()
+}
+
+/// one unfragmented text message
+pub fn send_text<W: Write>(out: &mut W, text: &str) -> std::io::Result<()> {
+    send(out, TEXT, text.as_bytes())
+}
+
+/// a final frame. the server doesn't mask its frames.
+pub fn send<W: Write>(out: &mut W, opcode: u8, payload: &[u8]) -> std::io::Result<()> {
+    let len = payload.len();
+    let mut header = vec![0x80 | opcode];
+    if len < 126 {
+        header.push(len as u8);
+    } else if len <= 0xffff {
+        header.push(126);
+        header.push((len >> 8) as u8);
+        header.push(len as u8);
+    } else {
+        header.push(127);
+        for i in (0..8).rev() {
+            header.push((len as u64 >> (i * 8)) as u8);
+        }
+    }
+    out.write_all(&header)?;
+    out.write_all(payload)?;
+    out.flush()
+}
+
+/// a frame from the client: its opcode and the unmasked payload.
+/// payloads longer than max_len are refused.
+pub fn read<R: Read>(input: &mut R, max_len: usize) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut header = [0u8; 2];
+    input.read_exact(&mut header)?;
+    let opcode = header[0] & 0x0f;
+    if header[1] & 0x80 == 0 {
+        return Err(invalid("client frames must be masked"));
+    }
+    let len = match header[1] & 0x7f {
+        126 => {
+            let mut len = [0u8; 2];
+            input.read_exact(&mut len)?;
+            (len[0] as u64) << 8 | len[1] as u64
+        }
+        127 => {
+            let mut len = [0u8; 8];
+            input.read_exact(&mut len)?;
+            len.iter().fold(0, |n, &b| n << 8 | b as u64)
+        }
+        len => len as u64,
+    };
+    if len > max_len as u64 {
+        return Err(invalid("frame too long"));
+    }
+    let mut mask = [0u8; 4];
+    input.read_exact(&mut mask)?;
+    let mut payload = vec![0; len as usize];
+    input.read_exact(&mut payload)?;
+    for (i, b) in payload.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+    Ok((opcode, payload))
+}
+
+fn invalid(why: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, why)
+}
+
+pub fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as usize) << 16 | (b[1] as usize) << 8 | b[2] as usize;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i)) & 0x3f] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// only for the handshake, where rfc 6455 asks for it
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    // pad to a multiple of 64 bytes: a 1 bit, zeros and the length in bits
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    let bits = data.len() as u64 * 8;
+    for i in (0..8).rev() {
+        msg.push((bits >> (i * 8)) as u8);
+    }
+
+    for block in msg.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = (block[i * 4] as u32) << 24 | (block[i * 4 + 1] as u32) << 16 |
+                   (block[i * 4 + 2] as u32) << 8 | block[i * 4 + 3] as u32;
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+        for (i, wi) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0...19 => ((b & c) | (!b & d), 0x5A827999),
+                20...39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40...59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a.rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*wi);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        h[0] = h[0].wrapping_add(a);
+        h[1] = h[1].wrapping_add(b);
+        h[2] = h[2].wrapping_add(c);
+        h[3] = h[3].wrapping_add(d);
+        h[4] = h[4].wrapping_add(e);
+    }
+
+    let mut digest = [0u8; 20];
+    for (i, word) in h.iter().enumerate() {
+        for j in 0..4 {
+            digest[i * 4 + j] = (word >> (24 - 8 * j)) as u8;
+        }
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_key() {
+        // the example from rfc 6455, section 1.3
+        let mut keyed = "dGhlIHNhbXBsZSBub25jZQ==".to_string();
+        keyed.push_str(ACCEPT_GUID);
+        assert_eq!(base64(&sha1(keyed.as_bytes())), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn sha1_digests() {
+        assert_eq!(base64(&sha1(b"")), "2jmj7l5rSw0yVb/vlWAYkK/YBwk=");
+        assert_eq!(base64(&sha1(b"abc")), "qZk+NkcGgWq6PiVxeFDCbJzQ2J0=");
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn masked_close() {
+        // a close with status 1000, masked as a client sends it
+        let frame = [0x88, 0x82, 1, 2, 3, 4, 0x03 ^ 1, 0xe8 ^ 2];
+        let (opcode, payload) = read(&mut &frame[..], 125).unwrap();
+        assert_eq!(opcode, CLOSE);
+        assert_eq!(payload, vec![0x03, 0xe8]);
+    }
+
+    #[test]
+    fn unmasked_or_long_frames_are_refused() {
+        assert!(read(&mut &[0x88, 0x00][..], 125).is_err());
+        assert!(read(&mut &[0x81, 0xfe, 0x01, 0x00][..], 125).is_err());
+    }
+}
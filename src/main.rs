@@ -11,6 +11,10 @@ extern crate serial;
 #[macro_use]
 extern crate clap;
 extern crate rand;
+extern crate serde;
+extern crate serde_json;
+#[macro_use]
+extern crate serde_derive;
 
 #[macro_use]
 extern crate bitflags;
@@ -25,19 +29,20 @@ mod animations;
 mod opc;
 mod osc;
 mod events;
+mod record;
+mod console;
+mod control;
+mod http;
+mod mqtt;
 mod config;
+mod scheduler;
+mod settings;
+mod status;
 
 #[cfg(feature = "gui")]
 extern crate kiss3d;
 #[cfg(feature = "gui")]
-extern crate serde;
-#[cfg(feature = "gui")]
-extern crate serde_json;
-#[cfg(feature = "gui")]
 extern crate nalgebra;
-#[cfg(feature = "gui")]
-#[macro_use]
-extern crate serde_derive;
 
 #[cfg(feature = "gui")]
 mod gui;
@@ -55,7 +60,7 @@ pub fn start_ui(&mut self) {
 }
 
 }
-pub fn create_gui() -> (Option<Box<super::pixels::LedArray+ Send>> , Option<UI>){
+pub fn create_gui(_size: usize, _layout: &str) -> (Option<Box<super::pixels::LedArray+ Send>> , Option<UI>){
     (None, None)
 }
 
@@ -65,12 +70,12 @@ pub fn create_gui() -> (Option<Box<super::pixels::LedArray+ Send>> , Option<UI>)
 
 use animations::Drawer;
 
-#[derive(Clone,Copy,Debug)]
+#[derive(Clone,Copy,Debug,Serialize,Deserialize)]
 pub enum EventTypes {
     Connect(usize, usize),
 }
 
-#[derive(Clone,Copy,Debug)]
+#[derive(Clone,Copy,Debug,Serialize,Deserialize)]
 pub enum Events {
     Start(EventTypes),
     Stop(EventTypes),
@@ -79,34 +84,66 @@ pub enum Events {
     ConfigChanged,
     SaveConfig,
     ModeChanged(Modes),
+    /// 0 is off, 1 is full brightness
+    BrightnessChanged(f32),
+    /// the touch sensor of a pole stopped reporting
+    PoleOffline(usize),
+    PoleOnline(usize),
 }
 
 
-#[derive(Clone,Copy,Debug)]
+#[derive(Clone,Copy,Debug,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Modes {
     Regular,
     Disco,
     Flower,
 }
 
+impl Modes {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Modes::Regular => "regular",
+            Modes::Disco => "disco",
+            Modes::Flower => "flower",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Modes> {
+        match name {
+            "regular" | "reg" => Some(Modes::Regular),
+            "disco" => Some(Modes::Disco),
+            "flower" => Some(Modes::Flower),
+            _ => None,
+        }
+    }
+}
+
 /// touch goes up to cp1 and twinkels / breathes like the heart, the hight it is the higher the lum.
 
-const LEDS_PER_STRING: usize = 150;
-const NUM_POLES: usize = 20;
+/// where to send pixels when there are no leds or gui and no opc server was given
+#[cfg(not(feature = "ledscape"))]
+const DEFAULT_OPC_SERVER: &str = "127.0.0.1:7890";
 
 struct PoleLedArrayAdapter<'a> {
     ls: &'a mut pixels::LedArray,
     pole_offset: usize,
     size: usize,
+    brightness: f32,
 }
 
 
 impl<'a> PoleLedArrayAdapter<'a> {
-    fn new(ls: &'a mut pixels::LedArray, pole_strip_size: usize, pole_strip_index: usize) -> Self {
+    fn new(ls: &'a mut pixels::LedArray,
+           pole_strip_size: usize,
+           pole_strip_index: usize,
+           brightness: f32)
+           -> Self {
         PoleLedArrayAdapter {
             ls: ls,
             pole_offset: pole_strip_size * pole_strip_index,
             size: pole_strip_size,
+            brightness: brightness,
         }
     }
 }
@@ -117,7 +154,9 @@ impl<'a> pixels::LedArray for PoleLedArrayAdapter<'a> {
     }
 
     fn set_color_rgba(&mut self, lednum: usize, r: u8, g: u8, b: u8, a: u8) {
-        self.ls.set_color_rgba(self.pole_offset + lednum, r, g, b, a);
+        let brightness = self.brightness;
+        let dim = |c: u8| (c as f32 * brightness) as u8;
+        self.ls.set_color_rgba(self.pole_offset + lednum, dim(r), dim(g), dim(b), a);
     }
 
     fn show(&mut self) -> std::io::Result<()> {
@@ -128,9 +167,11 @@ impl<'a> pixels::LedArray for PoleLedArrayAdapter<'a> {
 
 
 #[cfg(feature = "ledscape")]
-fn get_led_array() -> (Box<pixels::LedArray+ Send>,  Option<gui::UI>) {
+fn get_led_array(installation: config::Installation,
+                 _layout: &str)
+                 -> (Box<pixels::LedArray+ Send>,  Option<gui::UI>) {
     use pixels::LedArray;
-    let mut l = ledscape::LedscapeLedArray::new(LEDS_PER_STRING);
+    let mut l = ledscape::LedscapeLedArray::new(installation.leds_per_strip);
     for i in 0..l.len() {
         l.set_color_rgba(i, 255, 0, 0, 255);
     }
@@ -153,15 +194,17 @@ fn get_led_array() -> (Box<pixels::LedArray+ Send>,  Option<gui::UI>) {
 }
 
 #[cfg(not(feature = "ledscape"))]
-fn get_led_array() -> (Box<pixels::LedArray+ Send>,  Option<gui::UI>) {
-    match gui::create_gui() {
+fn get_led_array(installation: config::Installation,
+                 layout: &str)
+                 -> (Box<pixels::LedArray+ Send>,  Option<gui::UI>) {
+    match gui::create_gui(installation.leds_per_strip * installation.num_poles, layout) {
         (Some(l), gui) => (l,gui),
-        (None, gui) => (Box::new(get_opc_array("127.0.0.1:7890").expect("can't connect")), gui),
+        (None, gui) => (Box::new(get_opc_array(DEFAULT_OPC_SERVER, installation).expect("can't connect")), gui),
     }
 }
 
-fn get_opc_array(adrr: &str) -> std::io::Result<opc::OPCLedArray> {
-    Ok(opc::OPCLedArray::new(LEDS_PER_STRING * NUM_POLES, adrr))
+fn get_opc_array(adrr: &str, installation: config::Installation) -> std::io::Result<opc::OPCLedArray> {
+    Ok(opc::OPCLedArray::new(installation.leds_per_strip * installation.num_poles, adrr))
 }
 
 
@@ -199,22 +242,79 @@ fn main() {
                                     .value_name("RGB")
                                     .help("RGB order")
                                     .takes_value(true))
+                                .arg(clap::Arg::with_name("layout")
+                                    .long("layout")
+                                    .value_name("FILE")
+                                    .help("The led layout for the gui (default ./layout.json)")
+                                    .takes_value(true))
+                                .arg(clap::Arg::with_name("print_config")
+                                    .long("print-config")
+                                    .help("Print the effective settings and where each comes from, and exit"))
+                                .arg(clap::Arg::with_name("touch_listen")
+                                    .long("touch_listen")
+                                    .value_name("ADDR")
+                                    .help("The udp address to receive pole touches on (default 0.0.0.0:3134)")
+                                    .takes_value(true))
+                                .arg(clap::Arg::with_name("config_listen")
+                                    .long("config_listen")
+                                    .value_name("ADDR")
+                                    .help("The udp address of the osc config server (default 0.0.0.0:8134)")
+                                    .takes_value(true))
+                                .arg(clap::Arg::with_name("control_listen")
+                                    .long("control_listen")
+                                    .value_name("ADDRS")
+                                    .help("Serve the console on unix:<path> and/or tcp addresses, comma separated")
+                                    .takes_value(true))
+                                .arg(clap::Arg::with_name("http_listen")
+                                    .long("http_listen")
+                                    .value_name("ADDR")
+                                    .help("Serve the json api on this tcp address")
+                                    .takes_value(true))
+                                .arg(clap::Arg::with_name("mqtt_broker")
+                                    .long("mqtt_broker")
+                                    .value_name("ADDR")
+                                    .help("Publish the state to this mqtt broker and take commands from it")
+                                    .takes_value(true))
+                                .arg(clap::Arg::with_name("mqtt_topic")
+                                    .long("mqtt_topic")
+                                    .value_name("PREFIX")
+                                    .help("The prefix of the mqtt topics (default connect)")
+                                    .takes_value(true))
+                                .arg(clap::Arg::with_name("poles")
+                                    .long("poles")
+                                    .value_name("NUM")
+                                    .help("The number of poles (default 20)")
+                                    .takes_value(true))
+                                .arg(clap::Arg::with_name("leds_per_strip")
+                                    .long("leds_per_strip")
+                                    .value_name("NUM")
+                                    .help("The number of leds on each pole's strip (default 150)")
+                                    .takes_value(true))
+                                .arg(clap::Arg::with_name("record")
+                                    .long("record")
+                                    .value_name("FILE")
+                                    .help("Append the incoming events to a json lines file")
+                                    .takes_value(true))
+                                .arg(clap::Arg::with_name("record_max_size")
+                                    .long("record_max_size")
+                                    .value_name("BYTES")
+                                    .help("Rotate the recording when it gets this big (default 10485760)")
+                                    .takes_value(true))
                                .get_matches();
 
-    let device = matches.value_of("device")
-        .map(|s| s.to_string())
-        .unwrap_or(std::env::var("DEVICE").unwrap_or(String::new()));
-    let osc_server = matches.value_of("osc_server")
-        .map(|s| s.to_string())
-        .unwrap_or(std::env::var("OSC_SERVER").unwrap_or(String::new()));
-    let opc_server = matches.value_of("opc_server")
-        .map(|s| s.to_string())
-        .unwrap_or(std::env::var("OPC_SERVER").unwrap_or(String::new()));
-    let rgb = matches.value_of("rgb")
-        .map(|s| pixels::RgbOrder::new(s).expect("Invalid rgb value!"))
-        .unwrap_or(pixels::RgbOrder::Rgb);
-
-    let configfile = matches.value_of("configfile").unwrap_or("./config.txt");
+    // command line, then environment, then the config file, then the defaults
+    let settings = match settings::Settings::load(&matches) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if matches.is_present("print_config") {
+        settings.print();
+        return;
+    }
+    let rgb = settings.rgb_order();
 
 
     env_logger::init().unwrap();
@@ -222,14 +322,6 @@ fn main() {
     info!("hello");
     // TODO add OPCCLient
 
-    const h_shift : f32 = (2.0+0.5)*360.0/(NUM_POLES as f32);
-    const partition : f32 =  2_f32 * std::f32::consts::PI / (NUM_POLES as f32);
-
-    let poles: Vec<Pole> = (0..NUM_POLES)
-        .map(|x| if x % 2 == 0 { partition * (x as f32) + h_shift} else  { partition * (x as f32) - h_shift})
-        .map(|x| Pole::new(x))
-        .collect();
-
     let (tx, rx) = mpsc::channel();
 
     // 20 fps
@@ -243,12 +335,64 @@ fn main() {
         }
     });
 
-    let eventer = events::get_eventer(&device);
+    let recorder = if settings.record.value.is_empty() {
+        None
+    } else {
+        match record::Recorder::new(&settings.record.value, settings.record_max_size.value) {
+            Ok(recorder) => Some(recorder),
+            Err(e) => {
+                error!("can't record to {}: {}", settings.record.value, e);
+                None
+            }
+        }
+    };
+    // every sender but the draw thread's goes through here, so its events get recorded
+    let tap = |source: &'static str| match recorder {
+        Some(ref recorder) => recorder.tap(source, tx.clone()),
+        None => tx.clone(),
+    };
+
+    // the settings are resolved from all their layers by now, so the file is
+    // checked against the installation the leds will actually have
+    let installation = settings.installation();
+    let configfile = std::path::Path::new(&settings.config.value);
+    let config = config::Config::new(configfile, installation, tx.clone(), recorder.clone());
+
+    let num_poles = installation.num_poles;
+
+    let h_shift : f32 = (2.0+0.5)*360.0/(num_poles as f32);
+    let partition : f32 =  2_f32 * std::f32::consts::PI / (num_poles as f32);
+
+    let poles: Vec<Pole> = (0..num_poles)
+        .map(|x| if x % 2 == 0 { partition * (x as f32) + h_shift} else  { partition * (x as f32) - h_shift})
+        .map(|x| Pole::new(x, installation.leds_per_strip))
+        .collect();
+
+    let osc_server = settings.osc_server.value.clone();
+    let opc_server = settings.opc_server.value.clone();
+    let touch_listen = &settings.touch_listen.value;
+    let config_listen = &settings.config_listen.value;
+
+    let status = status::StatusHandle::new();
+
+    if let Err(e) = config.start_server(config_listen, status.clone()) {
+        error!("can't start the osc config server on {}: {}", config_listen, e);
+    }
+
+    let mut scheduler = scheduler::Scheduler::new(config.handle(), tap("scheduler"));
+    std::thread::spawn(move || scheduler.run());
+
+    let eventer = events::get_eventer(&settings.device.value, config.handle(), status.clone());
     let timeout = eventer.as_ref().map_or(std::time::Duration::from_secs(1000)
 , |e|e.get_timeout());
 
+    // touches pass through the debounce filter on their way to work
     if let Some(eventer) = eventer {
-        let newtx = tx.clone();
+        let newtx = if eventer.debounced() {
+            events::debounce(config.handle(), tap(eventer.name()))
+        } else {
+            tap(eventer.name())
+        };
         std::thread::spawn(move || {
             let tx = newtx;
             let mut eventer = eventer;
@@ -259,21 +403,55 @@ fn main() {
 
 
 
-    let newtx = tx.clone();
-    std::thread::spawn(move || {
-        use events::Eventer;
-        let tx = newtx;
-        let mut udpeventer = events::UDPEventSource::new();
-        udpeventer.get_events(tx);
-        panic!("event loop should be endless")
-    });
+    match events::UDPEventSource::new(touch_listen, config.handle()) {
+        Ok(mut udpeventer) => {
+            use events::Eventer;
+            let newtx = events::debounce(config.handle(), tap(udpeventer.name()));
+            std::thread::spawn(move || {
+                let tx = newtx;
+                udpeventer.get_events(tx);
+                panic!("event loop should be endless")
+            });
+        }
+        Err(e) => error!("can't listen for pole touches on {}: {}", touch_listen, e),
+    }
+
+    // touches from the control clients are debounced like those from stdin
+    let control_tx = events::debounce(config.handle(), tap("control"));
+    let control_listen = settings.control_listen.value.split(',').map(|a| a.trim());
+    for addr in control_listen.filter(|a| !a.is_empty()) {
+        if let Err(e) = control::start(addr, config.handle(), status.clone(), control_tx.clone()) {
+            error!("can't listen for control clients on {}: {}", addr, e);
+        }
+    }
+
+    // the websocket clients get the frames from work
+    let frames = http::Frames::new();
+    let http_listen = &settings.http_listen.value;
+    if !http_listen.is_empty() {
+        let http_tx = events::debounce(config.handle(), tap("http"));
+        if let Err(e) = http::start(http_listen,
+                                    config.handle(),
+                                    status.clone(),
+                                    frames.clone(),
+                                    http_tx) {
+            error!("can't start the http api on {}: {}", http_listen, e);
+        }
+    }
 
-    let config = config::Config::new(std::path::Path::new(configfile), tx.clone());
+    let mqtt_broker = &settings.mqtt_broker.value;
+    if !mqtt_broker.is_empty() {
+        mqtt::start(mqtt_broker,
+                    &settings.mqtt_topic.value,
+                    config.handle(),
+                    status.clone(),
+                    tap("mqtt"));
+    }
 
         let (ledscapecontroller, gui) : (Box<pixels::LedArray + Send>, Option<gui::UI> ) = if opc_server.is_empty() {
-            get_led_array()
+            get_led_array(installation, &settings.layout.value)
         } else {
-            (Box::new(get_opc_array(&opc_server).expect("can't connect")), None)
+            (Box::new(get_opc_array(&opc_server, installation).expect("can't connect")), None)
         };
 
     let ledscapecontroller : Box<pixels::LedArray + Send + 'static> = ledscapecontroller;
@@ -282,10 +460,12 @@ fn main() {
 
         let mut ledscapecontroller = pixels::RgbLedArray::new(ledscapecontroller, rgb);
 
-        let animator = animations::Animator::new(osc::OSCManager::new(&osc_server));
+        let animator = animations::Animator::new(osc::OSCManager::new(&osc_server, num_poles));
 
         work(config,
-            move |poles| draw_poles_to_array(&mut ledscapecontroller, poles),
+            status,
+            frames,
+            move |poles, brightness| draw_poles_to_array(&mut ledscapecontroller, poles, brightness),
             poles,
             timeout,
             animator,
@@ -327,14 +507,14 @@ impl TouchState {
     }
 }
 pub struct TouchMap {
-    pub touches: [[Option<TouchState>; NUM_POLES]; NUM_POLES],
+    pub touches: Vec<Vec<Option<TouchState>>>,
     timeout_period: std::time::Duration,
 }
 
 impl TouchMap {
-    pub fn new(timeout: std::time::Duration) -> Self {
+    pub fn new(num_poles: usize, timeout: std::time::Duration) -> Self {
         TouchMap {
-            touches: [[None; NUM_POLES]; NUM_POLES],
+            touches: vec![vec![None; num_poles]; num_poles],
             timeout_period: timeout,
         }
     }
@@ -353,8 +533,19 @@ impl TouchMap {
         }
     }
 
+    fn is_pole(&self, pole: usize) -> bool {
+        if pole >= self.touches.len() {
+            warn!("touch on unknown pole {}", pole);
+            return false;
+        }
+        true
+    }
+
     pub fn connect(&mut self, pole1: usize, pole2: usize) {
         //     let (pole1, pole2) = Self::normalize(pole1, pole2);
+        if !self.is_pole(pole1) || !self.is_pole(pole2) {
+            return;
+        }
 
         let mut newtouch = self.touches[pole1][pole2].unwrap_or(TouchState::new());
         newtouch.update();
@@ -364,6 +555,9 @@ impl TouchMap {
 
     pub fn disconnect(&mut self, pole1: usize, pole2: usize) {
         // let (pole1, pole2) = Self::normalize(pole1, pole2);
+        if !self.is_pole(pole1) || !self.is_pole(pole2) {
+            return;
+        }
         self.touches[pole1][pole2] = None;
         self.touches[pole2][pole1] = None;
     }
@@ -377,17 +571,20 @@ impl TouchMap {
 }
 
 fn work<F>(mut config: config::Config,
+           status: status::StatusHandle,
+           frames: http::Frames,
            mut draw_poles: F,
            mut poles: Vec<Pole>,
            timeout: std::time::Duration,
            mut animator: animations::Animator,
            receiver: mpsc::Receiver<Events>)
-    where F: FnMut(&mut [Pole])
+    where F: FnMut(&mut [Pole], f32)
 {
 
-    let mut touches = TouchMap::new(timeout);
+    let mut touches = TouchMap::new(poles.len(), timeout);
 
     let mut mode = Modes::Regular;
+    let mut brightness = 1.;
     let mut last_anim_time = std::time::Instant::now();
     for event in receiver.into_iter() {
         match event {
@@ -421,22 +618,27 @@ fn work<F>(mut config: config::Config,
 
                 animator.animate_hearts(&mut poles, delta);
 
-                draw_poles(&mut poles);
+                draw_poles(&mut poles, brightness);
+                status.update(|s| s.set_poles(&poles));
+                if let Some(pixels) = frames.wanted() {
+                    frames.publish(http::Frame {
+                        status: status.get(),
+                        pixels: if pixels { Some(render_rgb(&poles, brightness)) } else { None },
+                    });
+                }
 
                 last_anim_time = now;
             }
             Events::ConfigChanged => {
 
-                let polen = config.get_num_leds_for_pole();
-                let cp1 = config.get_cp1();
-                let cp2 = config.get_cp2();
-                let heart = config.get_heart();
-                for p in poles.iter_mut() {
-                    p.set_pole_length(polen);
-                    p.set_cp1(cp1);
-                    p.set_cp2(cp2);
-                    p.set_heart(&heart);
+                for (i, p) in poles.iter_mut().enumerate() {
+                    let geometry = config.get_pole_geometry(i);
+                    p.set_pole_length(geometry.num_leds_for_pole);
+                    p.set_cp1(geometry.cp1);
+                    p.set_cp2(geometry.cp2);
+                    p.set_heart(&geometry.heart);
                 }
+                animator.set_params(&config.get_anim_params());
             }
             Events::SaveConfig => {
                 config.save_config();
@@ -445,7 +647,19 @@ fn work<F>(mut config: config::Config,
 
                 clear(&mut poles);
                 mode = newmode;
-            }            
+                config.set_mode(newmode);
+                status.update(|s| s.mode = newmode);
+            }
+            Events::BrightnessChanged(newbrightness) => {
+                brightness = newbrightness;
+                status.update(|s| s.brightness = newbrightness);
+            }
+            Events::PoleOffline(pole) => {
+                status.update(|s| { s.offline.insert(pole); });
+            }
+            Events::PoleOnline(pole) => {
+                status.update(|s| { s.offline.remove(pole); });
+            }
         }
     }
 }
@@ -468,6 +682,16 @@ pub enum PoleState {
     ConnectedTo(bit_set::BitSet),
 }
 
+impl PoleState {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            PoleState::NotTouched => "not_touched",
+            PoleState::Touched => "touched",
+            PoleState::ConnectedTo(_) => "connected",
+        }
+    }
+}
+
 #[derive(Copy, Clone,Debug, PartialEq)]
 pub enum PoleAnimations {
     Touching,
@@ -475,6 +699,16 @@ pub enum PoleAnimations {
     Exploding,
 }
 
+impl PoleAnimations {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            PoleAnimations::Touching => "touching",
+            PoleAnimations::Connecting => "connecting",
+            PoleAnimations::Exploding => "exploding",
+        }
+    }
+}
+
 #[derive(Clone,Debug)]
 pub struct Pole {
     pub level: f32,
@@ -521,12 +755,12 @@ impl Pole {
     pub fn set_heart(&mut self, newl: &std::ops::Range<usize>) {
         self.heart = std::cmp::max(0, newl.start)..std::cmp::min(self.internal_leds.len(), newl.end);
     }
-    fn new(rads: f32) -> Self {
+    fn new(rads: f32, strip_length: usize) -> Self {
         Pole {
             level: 0.,
             touch_level: 0.,
             internal_leds:
-                vec![palette::Hsl::new(palette::RgbHue::from_radians(0.),1.,0.5); LEDS_PER_STRING],
+                vec![palette::Hsl::new(palette::RgbHue::from_radians(0.),1.,0.5); strip_length],
             //            pole_state : PoleState::Untouched,
             base_color: palette::Hsl::new(palette::RgbHue::from_radians(rads), 1., 0.5),
 
@@ -534,18 +768,19 @@ impl Pole {
 
             anim: None,
             state: PoleState::NotTouched,
-            pole_length: LEDS_PER_STRING,
-            cp1: LEDS_PER_STRING,
-            cp2: LEDS_PER_STRING,
+            pole_length: strip_length,
+            cp1: strip_length,
+            cp2: strip_length,
             heart: 0..0,
         }
     }
 }
 
-fn draw_poles_to_array(c: &mut pixels::LedArray, poles: &[Pole]) {
+fn draw_poles_to_array(c: &mut pixels::LedArray, poles: &[Pole], brightness: f32) {
     for (i, pole) in poles.iter().enumerate() {
-        // with ledscape, anim array  is a big array. each LEDS_PER_STRING are one pole.
-        let mut adaper = PoleLedArrayAdapter::new(c, LEDS_PER_STRING, i);
+        // with ledscape, anim array  is a big array. each strip length are one pole.
+        let strip_length = pole.internal_leds.len();
+        let mut adaper = PoleLedArrayAdapter::new(c, strip_length, i, brightness);
         pole.draw(&mut adaper);
     }
     if let Err(e) = c.show() {
@@ -553,6 +788,13 @@ fn draw_poles_to_array(c: &mut pixels::LedArray, poles: &[Pole]) {
     }
 }
 
+/// the colors as the leds show them, for the live stream
+fn render_rgb(poles: &[Pole], brightness: f32) -> Vec<u8> {
+    let mut buffer = pixels::RgbBuffer::new(poles.iter().map(|p| p.internal_leds.len()).sum());
+    draw_poles_to_array(&mut buffer, poles, brightness);
+    buffer.rgb
+}
+
 impl animations::Drawer for Pole {
     fn draw(&self, array: &mut pixels::LedArray) {
         // ?!
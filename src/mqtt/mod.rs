@@ -0,0 +1,251 @@
+use std;
+use std::net::TcpStream;
+
+use super::{Events, Modes, PoleState};
+use config;
+use status;
+
+mod packet;
+
+/// the broker drops us after 1.5 times this without a packet
+const KEEP_ALIVE_SECS: u16 = 30;
+
+/// how often the status is checked for changes to publish
+const POLL_MS: u64 = 200;
+
+/// publishes the state as retained topics under prefix, and takes commands:
+///
+/// <prefix>/online                      true, or false once we're gone
+/// <prefix>/mode                        regular, disco or flower
+/// <prefix>/pole/<n>/state              not_touched, touched or connected
+/// <prefix>/pole/<n>/connected_to       the other poles, comma separated
+/// <prefix>/pole/<n>/online             false while the pole's sensor is silent
+/// <prefix>/set/mode                    <- a mode name
+/// <prefix>/set/preset                  <- a preset name
+/// <prefix>/set/config/<key>            <- values, as the console's set command
+pub fn start(broker: &str,
+             prefix: &str,
+             config: config::ConfigHandle,
+             status: status::StatusHandle,
+             sender: std::sync::mpsc::Sender<Events>) {
+    let bridge = Bridge {
+        broker: broker.to_string(),
+        prefix: prefix.trim_right_matches('/').to_string(),
+        config: config.with_source("mqtt"),
+        status: status,
+        sender: sender,
+    };
+    std::thread::spawn(move || bridge.run());
+}
+
+#[derive(Clone)]
+struct Bridge {
+    broker: String,
+    prefix: String,
+    config: config::ConfigHandle,
+    status: status::StatusHandle,
+    sender: std::sync::mpsc::Sender<Events>,
+}
+
+impl Bridge {
+    /// stay connected: on a lost connection wait a bit, connect again and
+    /// publish everything anew
+    fn run(&self) {
+        let mut wait = 1;
+        loop {
+            let start = std::time::Instant::now();
+            if let Err(e) = self.session() {
+                warn!("mqtt broker {}: {}", self.broker, e);
+            }
+            // back off while the broker is down, not after a long session
+            if start.elapsed() > std::time::Duration::from_secs(60) {
+                wait = 1;
+            }
+            std::thread::sleep(std::time::Duration::from_secs(wait));
+            wait = std::cmp::min(wait * 2, 60);
+        }
+    }
+
+    fn session(&self) -> std::io::Result<()> {
+        let mut stream = TcpStream::connect(&self.broker[..])?;
+        let online = self.topic("online");
+        // the broker says we're gone when we can't
+        let will = packet::Will {
+            topic: &online,
+            payload: b"false",
+        };
+        packet::connect(&mut stream, &self.client_id(), KEEP_ALIVE_SECS, &will)?;
+        // a broker that takes the connection but never answers would hang us here
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(KEEP_ALIVE_SECS as u64)))?;
+        match packet::read(&mut stream)? {
+            packet::Packet::ConnAck { return_code: 0 } => {}
+            packet::Packet::ConnAck { return_code } => {
+                return Err(std::io::Error::new(std::io::ErrorKind::ConnectionRefused,
+                                               format!("connection refused, code {}",
+                                                       return_code)))
+            }
+            _ => {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                                               "no connack"))
+            }
+        }
+        stream.set_read_timeout(None)?;
+        packet::subscribe(&mut stream, 1, &self.topic("set/#"))?;
+        info!("connected to the mqtt broker {}", self.broker);
+
+        // commands are read on a thread of their own. when it ends the
+        // connection is shut, and the writes here fail
+        let mut input = stream.try_clone()?;
+        let commands = self.clone();
+        std::thread::spawn(move || {
+            loop {
+                match packet::read(&mut input) {
+                    // a retained command would be run again on every connect
+                    Ok(packet::Packet::Publish { retain: true, topic, .. }) => {
+                        warn!("ignoring the retained mqtt command {}", topic)
+                    }
+                    Ok(packet::Packet::Publish { topic, payload, .. }) => {
+                        commands.command(&topic, &String::from_utf8_lossy(&payload))
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        debug!("mqtt read ended: {}", e);
+                        let _ = input.shutdown(std::net::Shutdown::Both);
+                        return;
+                    }
+                }
+            }
+        });
+
+        packet::publish(&mut stream, &online, b"true", true)?;
+        let mut published = Published::new();
+        let mut last_ping = std::time::Instant::now();
+        loop {
+            self.publish_changes(&mut stream, &mut published)?;
+            if last_ping.elapsed() >= std::time::Duration::from_secs(KEEP_ALIVE_SECS as u64 / 2) {
+                packet::ping(&mut stream)?;
+                last_ping = std::time::Instant::now();
+            }
+            std::thread::sleep(std::time::Duration::from_millis(POLL_MS));
+        }
+    }
+
+    /// publish what changed since the last time, everything on a new connection
+    fn publish_changes(&self,
+                       out: &mut TcpStream,
+                       published: &mut Published)
+                       -> std::io::Result<()> {
+        let status = self.status.get();
+
+        let mode = status.mode.name();
+        if published.mode != Some(mode) {
+            packet::publish(out, &self.topic("mode"), mode.as_bytes(), true)?;
+            published.mode = Some(mode);
+        }
+
+        // nothing to say about the poles before the first draw
+        published.poles.resize(status.poles.len(), None);
+        for (i, pole) in status.poles.iter().enumerate() {
+            let connected_to = match pole.state {
+                PoleState::ConnectedTo(ref others) => {
+                    let others: Vec<String> = others.iter().map(|p| p.to_string()).collect();
+                    others.join(",")
+                }
+                _ => String::new(),
+            };
+            let state = PolePublished {
+                state: pole.state.name(),
+                connected_to: connected_to,
+                online: !status.offline.contains(i),
+            };
+            let old = published.poles[i].take();
+            if old.as_ref().map_or(true, |old| old.state != state.state) {
+                packet::publish(out, &self.pole_topic(i, "state"), state.state.as_bytes(), true)?;
+            }
+            if old.as_ref().map_or(true, |old| old.connected_to != state.connected_to) {
+                packet::publish(out,
+                                &self.pole_topic(i, "connected_to"),
+                                state.connected_to.as_bytes(),
+                                true)?;
+            }
+            if old.as_ref().map_or(true, |old| old.online != state.online) {
+                packet::publish(out,
+                                &self.pole_topic(i, "online"),
+                                state.online.to_string().as_bytes(),
+                                true)?;
+            }
+            published.poles[i] = Some(state);
+        }
+        Ok(())
+    }
+
+    fn command(&self, topic: &str, payload: &str) {
+        let payload = payload.trim();
+        let set = self.topic("set/");
+        if !topic.starts_with(&set) {
+            return;
+        }
+        let command = &topic[set.len()..];
+        debug!("mqtt command {} {:?}", command, payload);
+
+        let res = match command {
+            "mode" => {
+                match Modes::from_name(payload) {
+                    Some(mode) => {
+                        self.sender.send(Events::ModeChanged(mode));
+                        Ok(())
+                    }
+                    None => Err(format!("unknown mode {:?}", payload)),
+                }
+            }
+            "preset" => self.config.load_preset(payload).map_err(|e| e.to_string()),
+            _ if command.starts_with("config/") => {
+                let values: Vec<&str> = payload.split_whitespace().collect();
+                self.config
+                    .set(&command["config/".len()..], &values)
+                    .map_err(|e| e.to_string())
+            }
+            _ => Err("unknown command".to_string()),
+        };
+        if let Err(e) = res {
+            warn!("ignoring mqtt {} {:?}: {}", topic, payload, e);
+        }
+    }
+
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{}", self.prefix, name)
+    }
+
+    fn pole_topic(&self, pole: usize, name: &str) -> String {
+        format!("{}/pole/{}/{}", self.prefix, pole, name)
+    }
+
+    /// the same id on every connection, so a restart takes over the old session
+    fn client_id(&self) -> String {
+        let id = format!("connect-{}", self.prefix.replace('/', "-"));
+        // 23 characters is all that 3.1.1 brokers have to accept
+        id.chars().take(23).collect()
+    }
+}
+
+/// what the broker has from us on this connection
+struct Published {
+    mode: Option<&'static str>,
+    poles: Vec<Option<PolePublished>>,
+}
+
+impl Published {
+    fn new() -> Self {
+        Published {
+            mode: None,
+            poles: vec![],
+        }
+    }
+}
+
+#[derive(Clone)]
+struct PolePublished {
+    state: &'static str,
+    connected_to: String,
+    online: bool,
+}
@@ -0,0 +1,231 @@
+use std;
+use std::io::{Read, Write};
+
+/// the few packets of mqtt 3.1.1 the bridge uses, all at qos 0
+pub enum Packet {
+    ConnAck { return_code: u8 },
+    /// retain is set on what the broker kept from before we subscribed
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+        retain: bool,
+    },
+    SubAck,
+    PingResp,
+    /// anything else the broker sends, ignored
+    Other,
+}
+
+pub struct Will<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+}
+
+pub fn connect<W: Write>(out: &mut W,
+                         client_id: &str,
+                         keep_alive: u16,
+                         will: &Will)
+                         -> std::io::Result<()> {
+    let mut body = vec![];
+    put_str(&mut body, "MQTT");
+    // protocol level 4 is 3.1.1
+    body.push(4);
+    // clean session, a retained will at qos 0
+    body.push(0x02 | 0x04 | 0x20);
+    body.push((keep_alive >> 8) as u8);
+    body.push(keep_alive as u8);
+    put_str(&mut body, client_id);
+    put_str(&mut body, will.topic);
+    put_bytes(&mut body, will.payload);
+    send(out, 0x10, &body)
+}
+
+pub fn publish<W: Write>(out: &mut W,
+                         topic: &str,
+                         payload: &[u8],
+                         retain: bool)
+                         -> std::io::Result<()> {
+    let mut body = vec![];
+    put_str(&mut body, topic);
+    body.extend_from_slice(payload);
+    send(out, 0x30 | retain as u8, &body)
+}
+
+pub fn subscribe<W: Write>(out: &mut W, packet_id: u16, filter: &str) -> std::io::Result<()> {
+    let mut body = vec![(packet_id >> 8) as u8, packet_id as u8];
+    put_str(&mut body, filter);
+    // qos 0
+    body.push(0);
+    send(out, 0x82, &body)
+}
+
+pub fn ping<W: Write>(out: &mut W) -> std::io::Result<()> {
+    send(out, 0xc0, &[])
+}
+
+/// block until a whole packet came in
+pub fn read<R: Read>(input: &mut R) -> std::io::Result<Packet> {
+    let mut byte = [0u8];
+    input.read_exact(&mut byte)?;
+    let header = byte[0];
+
+    // the remaining length: 7 bits a byte, lowest first, at most 4 bytes
+    let mut len = 0usize;
+    for i in 0..4 {
+        input.read_exact(&mut byte)?;
+        len |= ((byte[0] & 0x7f) as usize) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        if i == 3 {
+            return Err(invalid("bad remaining length"));
+        }
+    }
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+
+    let packet = match header >> 4 {
+        2 if body.len() == 2 => Packet::ConnAck { return_code: body[1] },
+        3 => {
+            if body.len() < 2 {
+                return Err(invalid("short publish"));
+            }
+            let topic_len = (body[0] as usize) << 8 | body[1] as usize;
+            // qos 1 and 2 have a packet id after the topic
+            let id_len = if (header >> 1) & 0x03 > 0 { 2 } else { 0 };
+            if body.len() < 2 + topic_len + id_len {
+                return Err(invalid("short publish"));
+            }
+            let topic = String::from_utf8(body[2..2 + topic_len].to_vec())
+                .map_err(|_| invalid("topic is not utf-8"))?;
+            Packet::Publish {
+                topic: topic,
+                payload: body[2 + topic_len + id_len..].to_vec(),
+                retain: header & 0x01 != 0,
+            }
+        }
+        9 => Packet::SubAck,
+        13 => Packet::PingResp,
+        _ => Packet::Other,
+    };
+    Ok(packet)
+}
+
+fn send<W: Write>(out: &mut W, header: u8, body: &[u8]) -> std::io::Result<()> {
+    let mut packet = vec![header];
+    let mut len = body.len();
+    loop {
+        let mut byte = (len & 0x7f) as u8;
+        len >>= 7;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    out.write_all(&packet)?;
+    out.flush()
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    put_bytes(buf, s.as_bytes());
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.push((bytes.len() >> 8) as u8);
+    buf.push(bytes.len() as u8);
+    buf.extend_from_slice(bytes);
+}
+
+fn invalid(why: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, why)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a publish whose remaining length is len
+    fn publish_of_len(len: usize) -> Vec<u8> {
+        let mut buf = vec![];
+        // 2 bytes topic length and the 1 byte topic "t"
+        publish(&mut buf, "t", &vec![b'x'; len - 3], false).unwrap();
+        buf
+    }
+
+    #[test]
+    fn remaining_length() {
+        for &(len, encoded) in &[(127, &[0x7f][..]),
+                                 (128, &[0x80, 0x01][..]),
+                                 (16383, &[0xff, 0x7f][..]),
+                                 (16384, &[0x80, 0x80, 0x01][..])] {
+            let buf = publish_of_len(len);
+            assert_eq!(&buf[1..1 + encoded.len()], encoded, "length {}", len);
+            assert_eq!(buf.len(), 1 + encoded.len() + len);
+            match read(&mut &buf[..]).unwrap() {
+                Packet::Publish { topic, payload, .. } => {
+                    assert_eq!(topic, "t");
+                    assert_eq!(payload.len(), len - 3);
+                }
+                _ => panic!("not a publish"),
+            }
+        }
+    }
+
+    #[test]
+    fn remaining_length_of_5_bytes_is_refused() {
+        assert!(read(&mut &[0x30, 0x80, 0x80, 0x80, 0x80, 0x01][..]).is_err());
+    }
+
+    #[test]
+    fn publish_with_qos_skips_the_packet_id() {
+        // qos 1, topic "a/b", packet id 0x1234, payload "on"
+        let packet = [0x32, 9, 0, 3, b'a', b'/', b'b', 0x12, 0x34, b'o', b'n'];
+        match read(&mut &packet[..]).unwrap() {
+            Packet::Publish { topic, payload, retain } => {
+                assert_eq!(topic, "a/b");
+                assert_eq!(payload, b"on".to_vec());
+                assert!(!retain);
+            }
+            _ => panic!("not a publish"),
+        }
+    }
+
+    #[test]
+    fn retain_flag() {
+        for &retained in &[true, false] {
+            let mut buf = vec![];
+            publish(&mut buf, "connect/set/mode", b"disco", retained).unwrap();
+            match read(&mut &buf[..]).unwrap() {
+                Packet::Publish { retain, .. } => assert_eq!(retain, retained),
+                _ => panic!("not a publish"),
+            }
+        }
+    }
+
+    #[test]
+    fn connect_with_will() {
+        let mut buf = vec![];
+        let will = Will {
+            topic: "c/online",
+            payload: b"false",
+        };
+        connect(&mut buf, "id", 30, &will).unwrap();
+        let mut expected = vec![0x10, 31, 0, 4, b'M', b'Q', b'T', b'T', 4, 0x26, 0, 30];
+        expected.extend_from_slice(&[0, 2, b'i', b'd']);
+        expected.extend_from_slice(&[0, 8, b'c', b'/', b'o', b'n', b'l', b'i', b'n', b'e']);
+        expected.extend_from_slice(&[0, 5, b'f', b'a', b'l', b's', b'e']);
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn connack() {
+        match read(&mut &[0x20, 2, 0, 5][..]).unwrap() {
+            Packet::ConnAck { return_code } => assert_eq!(return_code, 5),
+            _ => panic!("not a connack"),
+        }
+    }
+}
@@ -47,12 +47,12 @@ bitflags! {
 pub struct OSCManager {
     sender: std::sync::mpsc::Sender<rosc::OscPacket>,
     risers: usize,
-    sound_state: [SoundState; super::NUM_POLES],
+    sound_state: Vec<SoundState>,
 }
 
 
 impl OSCManager {
-    pub fn new(addr: &str) -> Self {
+    pub fn new(addr: &str, num_poles: usize) -> Self {
         let (tx, rx) = std::sync::mpsc::channel();
         let addr = addr.to_string();
         std::thread::spawn(move || Self::sendmsg(addr, rx));
@@ -60,7 +60,7 @@ impl OSCManager {
         OSCManager {
             sender: tx,
             risers: 0,
-            sound_state: [SoundState::empty(); super::NUM_POLES],
+            sound_state: vec![SoundState::empty(); num_poles],
         }
     }
 
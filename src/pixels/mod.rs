@@ -136,6 +136,32 @@ impl<T: AsRef<LedArray+ Send> + AsMut<LedArray+ Send> + Send> LedArray for RgbLe
     }
 }
 
+/// the colors in memory, 3 bytes per led in rgb order. for the live stream
+pub struct RgbBuffer {
+    pub rgb: Vec<u8>,
+}
+
+impl RgbBuffer {
+    pub fn new(len: usize) -> Self {
+        RgbBuffer { rgb: vec![0; len * 3] }
+    }
+}
+
+impl LedArray for RgbBuffer {
+    fn len(&self) -> usize {
+        self.rgb.len() / 3
+    }
+
+    fn set_color_rgba(&mut self, lednum: usize, r: u8, g: u8, b: u8, _a: u8) {
+        if let Some(led) = self.rgb.get_mut(lednum * 3..lednum * 3 + 3) {
+            led.copy_from_slice(&[r, g, b]);
+        }
+    }
+
+    fn show(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
 
 pub fn set_color<U: Float, T: IntoColor<U>>(l: &mut LedArray, lednum: usize, color: T) {
     let rgb = color.into_rgb();
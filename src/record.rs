@@ -0,0 +1,118 @@
+use std;
+use std::io::Write;
+use serde_json;
+
+use super::Events;
+
+/// how many rotated files are kept next to the current one: <file>.1 is the newest
+const KEEP_FILES: usize = 5;
+
+/// one line of the recording
+#[derive(Clone,Debug,Serialize,Deserialize)]
+pub struct Entry {
+    /// seconds since the recording started, from a monotonic clock
+    pub t: f64,
+    /// what sent the event: serial, udp, stdin, scheduler, osc <address>...
+    pub source: String,
+    pub event: Events,
+}
+
+/// appends the events to a json lines file, one entry per line
+#[derive(Clone)]
+pub struct Recorder {
+    log: std::sync::Arc<std::sync::Mutex<Log>>,
+}
+
+struct Log {
+    path: std::path::PathBuf,
+    file: std::fs::File,
+    size: u64,
+    max_size: u64,
+    start: std::time::Instant,
+}
+
+impl Recorder {
+    /// a file bigger than max_size bytes is rotated
+    pub fn new(path: &str, max_size: u64) -> std::io::Result<Self> {
+        let path = std::path::PathBuf::from(path);
+        let file = open(&path)?;
+        let size = file.metadata()?.len();
+        let log = Log {
+            path: path,
+            file: file,
+            size: size,
+            max_size: max_size,
+            start: std::time::Instant::now(),
+        };
+        Ok(Recorder { log: std::sync::Arc::new(std::sync::Mutex::new(log)) })
+    }
+
+    pub fn record(&self, source: &str, event: Events) {
+        if let Events::Draw = event {
+            return;
+        }
+        let mut log = self.log.lock().unwrap();
+        if let Err(e) = log.write(source, event) {
+            warn!("can't record to {:?}: {}", log.path, e);
+        }
+    }
+
+    /// events sent to the returned sender are recorded as coming from source,
+    /// and passed on to sender.
+    pub fn tap(&self, source: &'static str, sender: std::sync::mpsc::Sender<Events>)
+               -> std::sync::mpsc::Sender<Events> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let recorder = self.clone();
+        std::thread::spawn(move || {
+            for event in rx.iter() {
+                recorder.record(source, event);
+                sender.send(event);
+            }
+        });
+        tx
+    }
+}
+
+impl Log {
+    fn write(&mut self, source: &str, event: Events) -> std::io::Result<()> {
+        let elapsed = self.start.elapsed();
+        let entry = Entry {
+            t: elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.,
+            source: source.to_string(),
+            event: event,
+        };
+        let mut line = serde_json::to_vec(&entry)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.size += line.len() as u64;
+
+        if self.size >= self.max_size {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    /// <file> becomes <file>.1, <file>.1 becomes <file>.2 and so on
+    fn rotate(&mut self) -> std::io::Result<()> {
+        for i in (1..KEEP_FILES).rev() {
+            // older files may not exist yet
+            let _ = std::fs::rename(numbered(&self.path, i), numbered(&self.path, i + 1));
+        }
+        std::fs::rename(&self.path, numbered(&self.path, 1))?;
+        self.file = open(&self.path)?;
+        self.size = 0;
+        info!("rotated the recording {:?}", self.path);
+        Ok(())
+    }
+}
+
+fn open(path: &std::path::Path) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn numbered(path: &std::path::Path, i: usize) -> std::path::PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", i));
+    std::path::PathBuf::from(name)
+}
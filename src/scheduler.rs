@@ -0,0 +1,324 @@
+use std;
+use libc;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{Events, Modes};
+use config;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+const MINUTES_PER_WEEK: u32 = 7 * MINUTES_PER_DAY;
+
+#[derive(Clone,Copy,Debug,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Sun,
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+}
+
+const ALL_DAYS: [Weekday; 7] = [Weekday::Sun,
+                                Weekday::Mon,
+                                Weekday::Tue,
+                                Weekday::Wed,
+                                Weekday::Thu,
+                                Weekday::Fri,
+                                Weekday::Sat];
+
+/// a local time of day, written as "HH:MM" in the config file
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct ClockTime {
+    minutes: u32,
+}
+
+impl ClockTime {
+    pub fn parse(s: &str) -> Option<ClockTime> {
+        let mut parts = s.splitn(2, ':');
+        let hours = parts.next().and_then(|h| h.trim().parse::<u32>().ok());
+        let minutes = parts.next().and_then(|m| m.trim().parse::<u32>().ok());
+        match (hours, minutes) {
+            (Some(h), Some(m)) if h < 24 && m < 60 => Some(ClockTime { minutes: h * 60 + m }),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for ClockTime {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        format!("{:02}:{:02}", self.minutes / 60, self.minutes % 60).serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for ClockTime {
+    fn deserialize<D>(d: D) -> Result<ClockTime, D::Error>
+        where D: Deserializer<'de>
+    {
+        use serde::de::Error;
+        let s = String::deserialize(d)?;
+        ClockTime::parse(&s).ok_or_else(|| D::Error::custom(format!("invalid time {:?}", s)))
+    }
+}
+
+/// from `at` on each of `days` (every day if empty), switch to `mode` and `brightness`.
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
+pub struct Slot {
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    pub at: ClockTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<Modes>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<f32>,
+}
+
+/// leds are off from `from` until `to` on each of `days` (every day if empty).
+/// a window that ends before it starts goes past midnight.
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
+pub struct Window {
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    pub from: ClockTime,
+    pub to: ClockTime,
+}
+
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
+#[serde(default)]
+pub struct Schedule {
+    pub slots: Vec<Slot>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blackout: Option<Window>,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Schedule {
+            slots: vec![],
+            blackout: None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), config::ConfigError> {
+        for slot in self.slots.iter() {
+            if let Some(brightness) = slot.brightness {
+                if !(brightness >= 0. && brightness <= 1.) {
+                    return Err(config::ConfigError::InvalidBrightness(brightness));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// the slot that is active at the given minute of the week, with the minute
+    /// of the week its current occurrence started at.
+    fn active_slot(&self, now: u32) -> Option<(usize, u32)> {
+        let mut latest: Option<(usize, u32)> = None;
+        let mut last_of_week: Option<(usize, u32)> = None;
+        for (i, slot) in self.slots.iter().enumerate() {
+            for start in starts(&slot.days, slot.at) {
+                if start <= now && latest.map_or(true, |(_, s)| start >= s) {
+                    latest = Some((i, start));
+                }
+                if last_of_week.map_or(true, |(_, s)| start >= s) {
+                    last_of_week = Some((i, start));
+                }
+            }
+        }
+        // nothing started yet this week, so the last slot of last week is still on
+        latest.or(last_of_week)
+    }
+
+    fn in_blackout(&self, now: u32) -> bool {
+        let window = match self.blackout {
+            Some(ref window) => window,
+            None => return false,
+        };
+        let length = (window.to.minutes + MINUTES_PER_DAY - window.from.minutes) % MINUTES_PER_DAY;
+        starts(&window.days, window.from)
+            .into_iter()
+            .any(|start| {
+                // a window that started late last week can still be on
+                let now = if now < start { now + MINUTES_PER_WEEK } else { now };
+                now < start + length
+            })
+    }
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Schedule::new()
+    }
+}
+
+/// the minutes of the week at which something happening at `at` on `days` starts
+fn starts(days: &[Weekday], at: ClockTime) -> Vec<u32> {
+    let days = if days.is_empty() { &ALL_DAYS[..] } else { days };
+    days.iter().map(|day| (*day as u32) * MINUTES_PER_DAY + at.minutes).collect()
+}
+
+/// the current local minute of the week, starting sunday 00:00
+fn now() -> u32 {
+    unsafe {
+        let t = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&t, &mut tm);
+        (tm.tm_wday as u32) * MINUTES_PER_DAY + (tm.tm_hour as u32) * 60 + tm.tm_min as u32
+    }
+}
+
+/// sends the mode and brightness of each slot when it starts. nothing is sent
+/// between slots, so a mode set by hand stays until the next slot.
+pub struct Scheduler {
+    config: config::ConfigHandle,
+    sender: std::sync::mpsc::Sender<Events>,
+    /// when the active slot started, and what it sets
+    current_slot: Option<(u32, Slot)>,
+    blackout: bool,
+}
+
+impl Scheduler {
+    pub fn new(config: config::ConfigHandle, sender: std::sync::mpsc::Sender<Events>) -> Self {
+        Scheduler {
+            config: config,
+            sender: sender,
+            current_slot: None,
+            blackout: false,
+        }
+    }
+
+    pub fn run(&mut self) {
+        info!("scheduler up");
+        loop {
+            self.tick(now());
+            std::thread::sleep(std::time::Duration::from_secs(10));
+        }
+    }
+
+    fn tick(&mut self, now: u32) {
+        let schedule = self.config.get_schedule();
+        // a slot is told by its start and what it sets, not by its place in the
+        // list, so an edit elsewhere in the schedule doesn't start it over
+        let slot = schedule.active_slot(now).map(|(i, start)| (start, schedule.slots[i].clone()));
+        let blackout = schedule.in_blackout(now);
+
+        if slot == self.current_slot && blackout == self.blackout {
+            return;
+        }
+        // the mode is sent again only for a new slot, or an edit of the active
+        // slot's mode, so a mode set by hand stays through other edits
+        let mode_changed = match (&slot, &self.current_slot) {
+            (&Some((start, ref new)), &Some((old_start, ref old))) => {
+                start != old_start || new.mode != old.mode
+            }
+            (&None, &None) => false,
+            _ => true,
+        };
+        self.current_slot = slot;
+        self.blackout = blackout;
+
+        let slot = self.current_slot.as_ref().map(|&(_, ref slot)| slot);
+
+        if mode_changed {
+            if let Some(mode) = slot.and_then(|slot| slot.mode) {
+                info!("scheduled mode {}", mode.name());
+                self.sender.send(Events::ModeChanged(mode));
+            }
+        }
+
+        let brightness = if blackout {
+            0.
+        } else {
+            slot.and_then(|slot| slot.brightness).unwrap_or(1.)
+        };
+        info!("scheduled brightness {}", brightness);
+        self.sender.send(Events::BrightnessChanged(brightness));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn schedule(json: &str) -> Schedule {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn at(day: Weekday, time: &str) -> u32 {
+        starts(&[day], ClockTime::parse(time).unwrap())[0]
+    }
+
+    fn sent(rx: &std::sync::mpsc::Receiver<Events>) -> String {
+        let events: Vec<Events> = rx.try_iter().collect();
+        format!("{:?}", events)
+    }
+
+    #[test]
+    fn blackout_past_midnight() {
+        let s = schedule(r#"{"blackout": {"days": ["fri"], "from": "23:00", "to": "06:00"}}"#);
+        assert!(!s.in_blackout(at(Weekday::Fri, "22:59")));
+        assert!(s.in_blackout(at(Weekday::Fri, "23:00")));
+        assert!(s.in_blackout(at(Weekday::Sat, "05:59")));
+        assert!(!s.in_blackout(at(Weekday::Sat, "06:00")));
+        assert!(!s.in_blackout(at(Weekday::Thu, "23:30")));
+    }
+
+    #[test]
+    fn blackout_from_saturday_into_sunday() {
+        let s = schedule(r#"{"blackout": {"days": ["sat"], "from": "22:00", "to": "07:00"}}"#);
+        assert!(!s.in_blackout(at(Weekday::Sat, "21:59")));
+        assert!(s.in_blackout(at(Weekday::Sat, "23:59")));
+        assert!(s.in_blackout(at(Weekday::Sun, "00:00")));
+        assert!(s.in_blackout(at(Weekday::Sun, "06:59")));
+        assert!(!s.in_blackout(at(Weekday::Sun, "07:00")));
+    }
+
+    #[test]
+    fn last_slot_of_last_week_stays_on() {
+        let s = schedule(r#"{"slots": [{"days": ["mon"], "at": "08:00", "mode": "disco"},
+                                       {"days": ["fri"], "at": "20:00", "mode": "flower"}]}"#);
+        let friday = at(Weekday::Fri, "20:00");
+        assert_eq!(s.active_slot(at(Weekday::Sun, "12:00")), Some((1, friday)));
+        assert_eq!(s.active_slot(at(Weekday::Mon, "07:59")), Some((1, friday)));
+        assert_eq!(s.active_slot(at(Weekday::Mon, "08:00")), Some((0, at(Weekday::Mon, "08:00"))));
+        assert_eq!(s.active_slot(at(Weekday::Sat, "01:00")), Some((1, friday)));
+    }
+
+    #[test]
+    fn editing_another_slot_keeps_the_mode() {
+        let (tx, _) = std::sync::mpsc::channel();
+        let config = config::Config::new(std::path::Path::new("/nonexistent/config.json"),
+                                         config::Installation::new(),
+                                         tx,
+                                         None);
+        let patch = |json: &str| {
+            config.handle().patch(&serde_json::from_str(json).unwrap()).unwrap();
+        };
+        patch(r#"{"schedule": {"slots": [{"days": ["fri"], "at": "20:00", "mode": "flower"}]}}"#);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut scheduler = Scheduler::new(config.handle(), tx);
+        scheduler.tick(at(Weekday::Fri, "21:00"));
+        assert_eq!(sent(&rx), "[ModeChanged(Flower), BrightnessChanged(1.0)]");
+
+        // a slot added in front of the active one moves it down the list
+        patch(r#"{"schedule": {"slots": [{"days": ["mon"], "at": "08:00", "mode": "disco"},
+                                         {"days": ["fri"], "at": "20:00", "mode": "flower"}]}}"#);
+        scheduler.tick(at(Weekday::Fri, "21:01"));
+        assert_eq!(sent(&rx), "[]");
+
+        // the active slot's brightness changes, its mode doesn't
+        patch(r#"{"schedule": {"slots": [{"days": ["mon"], "at": "08:00", "mode": "disco"},
+                                         {"days": ["fri"], "at": "20:00", "mode": "flower",
+                                          "brightness": 0.5}]}}"#);
+        scheduler.tick(at(Weekday::Fri, "21:02"));
+        assert_eq!(sent(&rx), "[BrightnessChanged(0.5)]");
+
+        scheduler.tick(at(Weekday::Mon, "08:00"));
+        assert_eq!(sent(&rx), "[ModeChanged(Disco), BrightnessChanged(1.0)]");
+    }
+}
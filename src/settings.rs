@@ -0,0 +1,269 @@
+use std;
+use clap;
+use serde_json;
+
+use config;
+use pixels;
+
+/// where a setting got its value. later layers win:
+/// defaults < config file < environment < command line
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Source {
+    Default,
+    ConfigFile,
+    Env(&'static str),
+    Cli(&'static str),
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Source::Default => write!(f, "default"),
+            Source::ConfigFile => write!(f, "config file"),
+            Source::Env(var) => write!(f, "environment {}", var),
+            Source::Cli(arg) => write!(f, "command line --{}", arg),
+        }
+    }
+}
+
+#[derive(Clone,Debug)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+/// the startup settings, merged from all the layers
+pub struct Settings {
+    pub config: Setting<String>,
+    /// serial device, "stdin" or "null". empty to auto detect a serial device
+    pub device: Setting<String>,
+    pub rgb: Setting<String>,
+    /// the json layout of the leds for the gui
+    pub layout: Setting<String>,
+    /// empty to not send osc
+    pub osc_server: Setting<String>,
+    /// empty to use the local leds or the gui
+    pub opc_server: Setting<String>,
+    pub touch_listen: Setting<String>,
+    pub config_listen: Setting<String>,
+    /// where the control console listens, comma separated. empty for nowhere
+    pub control_listen: Setting<String>,
+    /// where the json api listens. empty for nowhere
+    pub http_listen: Setting<String>,
+    /// the mqtt broker to bridge to. empty for none
+    pub mqtt_broker: Setting<String>,
+    pub mqtt_topic: Setting<String>,
+    pub poles: Setting<usize>,
+    pub leds_per_strip: Setting<usize>,
+    /// json lines file to record the events to. empty to not record
+    pub record: Setting<String>,
+    /// bytes after which the recording is rotated
+    pub record_max_size: Setting<u64>,
+}
+
+impl Settings {
+    /// the command line arguments have the same names as the config file keys
+    pub fn load(matches: &clap::ArgMatches) -> Result<Settings, String> {
+        let config = Layers::new(matches, None)
+            .get("config", "CONFIG", &[], "./config.json".to_string(), as_string)?;
+        let layers = Layers::new(matches, read_file(&config.value));
+
+        let network = config::NetworkConfig::new();
+        let installation = config::Installation::new();
+
+        let settings = Settings {
+            device: layers.get("device",
+                               "DEVICE",
+                               &["hardware", "device"],
+                               String::new(),
+                               as_string)?,
+            rgb: layers.get("rgb",
+                            "RGB",
+                            &["hardware", "rgb"],
+                            "rgb".to_string(),
+                            as_string)?,
+            layout: layers.get("layout",
+                               "LAYOUT",
+                               &["hardware", "layout"],
+                               "./layout.json".to_string(),
+                               as_string)?,
+            osc_server: layers.get("osc_server",
+                                   "OSC_SERVER",
+                                   &["network", "osc_server"],
+                                   String::new(),
+                                   as_string)?,
+            opc_server: layers.get("opc_server",
+                                   "OPC_SERVER",
+                                   &["network", "opc_server"],
+                                   String::new(),
+                                   as_string)?,
+            touch_listen: layers.get("touch_listen",
+                                     "TOUCH_LISTEN",
+                                     &["network", "touch_listen"],
+                                     network.touch_listen,
+                                     as_string)?,
+            config_listen: layers.get("config_listen",
+                                      "CONFIG_LISTEN",
+                                      &["network", "config_listen"],
+                                      network.config_listen,
+                                      as_string)?,
+            control_listen: layers.get("control_listen",
+                                       "CONTROL_LISTEN",
+                                       &["network", "control_listen"],
+                                       String::new(),
+                                       as_string)?,
+            http_listen: layers.get("http_listen",
+                                    "HTTP_LISTEN",
+                                    &["network", "http_listen"],
+                                    String::new(),
+                                    as_string)?,
+            mqtt_broker: layers.get("mqtt_broker",
+                                    "MQTT_BROKER",
+                                    &["network", "mqtt_broker"],
+                                    String::new(),
+                                    as_string)?,
+            mqtt_topic: layers.get("mqtt_topic",
+                                   "MQTT_TOPIC",
+                                   &["network", "mqtt_topic"],
+                                   "connect".to_string(),
+                                   as_string)?,
+            poles: layers.get("poles",
+                              "POLES",
+                              &["installation", "num_poles"],
+                              installation.num_poles,
+                              as_usize)?,
+            leds_per_strip: layers.get("leds_per_strip",
+                                       "LEDS_PER_STRIP",
+                                       &["installation", "leds_per_strip"],
+                                       installation.leds_per_strip,
+                                       as_usize)?,
+            record: layers.get("record", "RECORD", &[], String::new(), as_string)?,
+            record_max_size: layers.get("record_max_size",
+                                        "RECORD_MAX_SIZE",
+                                        &[],
+                                        10 * 1024 * 1024,
+                                        |v| v.as_u64())?,
+            config: config,
+        };
+
+        if pixels::RgbOrder::new(&settings.rgb.value).is_err() {
+            return Err(format!("invalid rgb order {:?} from {}",
+                               settings.rgb.value,
+                               settings.rgb.source));
+        }
+        // checked here, before the config file is validated against it
+        if let Err(e) = settings.installation().validate() {
+            return Err(format!("{} (poles from {}, leds_per_strip from {})",
+                               e,
+                               settings.poles.source,
+                               settings.leds_per_strip.source));
+        }
+        Ok(settings)
+    }
+
+    pub fn installation(&self) -> config::Installation {
+        config::Installation {
+            num_poles: self.poles.value,
+            leds_per_strip: self.leds_per_strip.value,
+        }
+    }
+
+    pub fn rgb_order(&self) -> pixels::RgbOrder {
+        pixels::RgbOrder::new(&self.rgb.value).expect("checked on load")
+    }
+
+    /// every setting with its value and where it came from, one per line
+    pub fn print(&self) {
+        fn line<T: std::fmt::Debug>(name: &str, s: &Setting<T>) {
+            println!("{:<16}{:<28}({})", name, format!("{:?}", s.value), s.source);
+        }
+        line("config", &self.config);
+        line("device", &self.device);
+        line("rgb", &self.rgb);
+        line("layout", &self.layout);
+        line("osc_server", &self.osc_server);
+        line("opc_server", &self.opc_server);
+        line("touch_listen", &self.touch_listen);
+        line("config_listen", &self.config_listen);
+        line("control_listen", &self.control_listen);
+        line("http_listen", &self.http_listen);
+        line("mqtt_broker", &self.mqtt_broker);
+        line("mqtt_topic", &self.mqtt_topic);
+        line("poles", &self.poles);
+        line("leds_per_strip", &self.leds_per_strip);
+        line("record", &self.record);
+        line("record_max_size", &self.record_max_size);
+    }
+}
+
+struct Layers<'a, 'b: 'a> {
+    matches: &'a clap::ArgMatches<'b>,
+    file: Option<serde_json::Value>,
+}
+
+impl<'a, 'b> Layers<'a, 'b> {
+    fn new(matches: &'a clap::ArgMatches<'b>, file: Option<serde_json::Value>) -> Self {
+        Layers {
+            matches: matches,
+            file: file,
+        }
+    }
+
+    /// the value of the first layer that has one, from the command line down to the default.
+    /// path is the key in the config file, e.g. ["network", "osc_server"].
+    fn get<T, F>(&self,
+                 arg: &'static str,
+                 env: &'static str,
+                 path: &[&str],
+                 default: T,
+                 from_file: F)
+                 -> Result<Setting<T>, String>
+        where T: std::str::FromStr,
+              F: Fn(&serde_json::Value) -> Option<T>
+    {
+        let parse = |value: &str, source: Source| {
+            value.parse::<T>()
+                .map(|value| Setting { value: value, source: source })
+                .map_err(|_| format!("invalid value {:?} from {}", value, source))
+        };
+
+        if let Some(value) = self.matches.value_of(arg) {
+            return parse(value, Source::Cli(arg));
+        }
+        if let Ok(value) = std::env::var(env) {
+            return parse(&value, Source::Env(env));
+        }
+        let mut file = self.file.as_ref();
+        for key in path {
+            file = file.and_then(|v| v.get(*key));
+        }
+        match file {
+            Some(value) if !path.is_empty() && !value.is_null() => {
+                match from_file(value) {
+                    Some(value) => Ok(Setting { value: value, source: Source::ConfigFile }),
+                    None => Err(format!("invalid {} in the config file", path.join("."))),
+                }
+            }
+            _ => {
+                Ok(Setting {
+                    value: default,
+                    source: Source::Default,
+                })
+            }
+        }
+    }
+}
+
+/// the config file as plain json. a missing or broken file is reported when
+/// the config is loaded, here it just doesn't take part.
+fn read_file(path: &str) -> Option<serde_json::Value> {
+    std::fs::File::open(path).ok().and_then(|f| serde_json::from_reader(f).ok())
+}
+
+fn as_string(v: &serde_json::Value) -> Option<String> {
+    v.as_str().map(|s| s.to_string())
+}
+
+fn as_usize(v: &serde_json::Value) -> Option<usize> {
+    v.as_u64().map(|n| n as usize)
+}
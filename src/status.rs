@@ -0,0 +1,69 @@
+use std;
+use bit_set::BitSet;
+
+use super::{Modes, Pole, PoleAnimations, PoleState};
+
+/// what the installation is doing right now. written by the work loop, read by
+/// the control interfaces.
+#[derive(Clone,Debug)]
+pub struct Status {
+    /// poles whose touch sensor isn't heard from
+    pub offline: BitSet,
+    pub mode: Modes,
+    pub brightness: f32,
+    /// as of the last draw. empty until the first one
+    pub poles: Vec<PoleStatus>,
+}
+
+/// the part of a pole the control interfaces show
+#[derive(Clone,Debug)]
+pub struct PoleStatus {
+    pub state: PoleState,
+    pub anim: Option<PoleAnimations>,
+    pub level: f32,
+    pub touch_level: f32,
+}
+
+impl Status {
+    pub fn new() -> Self {
+        Status {
+            offline: BitSet::new(),
+            mode: Modes::Regular,
+            brightness: 1.,
+            poles: vec![],
+        }
+    }
+
+    pub fn set_poles(&mut self, poles: &[Pole]) {
+        self.poles.clear();
+        self.poles.extend(poles.iter().map(|p| {
+            PoleStatus {
+                state: p.state.clone(),
+                anim: p.anim,
+                level: p.level,
+                touch_level: p.touch_level,
+            }
+        }));
+    }
+}
+
+#[derive(Clone)]
+pub struct StatusHandle {
+    status: std::sync::Arc<std::sync::RwLock<Status>>,
+}
+
+impl StatusHandle {
+    pub fn new() -> Self {
+        StatusHandle { status: std::sync::Arc::new(std::sync::RwLock::new(Status::new())) }
+    }
+
+    pub fn get(&self) -> Status {
+        self.status.read().unwrap().clone()
+    }
+
+    pub fn update<F>(&self, f: F)
+        where F: FnOnce(&mut Status)
+    {
+        f(&mut self.status.write().unwrap());
+    }
+}